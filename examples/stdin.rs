@@ -1,6 +1,6 @@
 extern crate shout;
 
-use std::io::{self, Read};
+use std::io;
 
 fn main() {
     let conn = shout::ShoutConnBuilder::new()
@@ -14,19 +14,10 @@ fn main() {
         .build().unwrap();
 
     println!("Connected to server");
-    let mut buffer = Vec::new();
-    io::stdin().read_to_end(&mut buffer).unwrap();
-    let step = 4096;
-    let mut pos = 0;
-    loop {
-        if pos + step < buffer.len() {
-            conn.send(&buffer[pos..(pos+step)].to_vec()).unwrap();
-            pos += step;
-            conn.sync();
-        } else {
-            conn.send(&buffer[pos..(pos+(buffer.len() - pos))].to_vec()).unwrap();
-            println!("Finished!");
-            break;
-        }
+    let stdin = io::stdin();
+    let summary = conn.stream_from(stdin.lock(), shout::ShoutStreamOptions::new());
+    if let Some(e) = summary.error {
+        panic!("Streaming failed: {}", e);
     }
+    println!("Finished! Sent {} bytes in {:?}", summary.bytes_sent, summary.elapsed);
 }
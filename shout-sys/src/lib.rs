@@ -79,8 +79,8 @@ extern "C" {
     /// *Note*: `ShoutFormat::WebmAudio` isn't accepted by this function.  Use
     /// `ShoutFormat::Webm` and `ShoutUsage::Audio`.
     ///
-    /// *Note*: This isn't exposed by the higher-level library, which assumes
-    /// the usage will always be `Audio`.
+    /// *Note*: The higher-level library exposes this as
+    /// `ShoutConn::set_content_format`, defaulting the usage to `Audio`.
     pub fn shout_set_content_format(shout: *mut Shout, format: c_uint, usage: c_uint, codecs: *const c_char) -> c_int;
     /// Returns the content format parameters as set by
     /// `shout_set_content_format`.
@@ -0,0 +1,188 @@
+//! Tokio based connections which never block the executor.
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::io::AsyncWrite;
+use tokio::time::{self, Sleep};
+
+use {ShoutConn, ShoutConnBuilder, ShoutConnError, ShoutConnState, ShoutNonblockingConn};
+
+/// How long to wait before polling libshout again while it is busy. libshout doesn't expose its
+/// socket, so readiness can't be registered with the reactor.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Polls an optional timer, clearing it once it has elapsed.
+fn poll_sleep(sleep: &mut Option<Pin<Box<Sleep>>>, cx: &mut Context) -> Poll<()> {
+    if let Some(ref mut s) = *sleep {
+        if s.as_mut().poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+    }
+    *sleep = None;
+    Poll::Ready(())
+}
+
+impl ShoutConnBuilder {
+    /// Configures a libshout handle in nonblocking mode, returning a future which connects it to
+    /// the host. The future must be polled by a Tokio runtime with the time driver enabled.
+    pub fn build_async(self) -> Result<ShoutAsyncOpen, ShoutConnError> {
+        ShoutAsyncConn::open(self.configure()?)
+    }
+}
+
+/// Future returned by `ShoutAsyncConn::open`, resolving once the connection is established
+pub struct ShoutAsyncOpen {
+    conn: Option<ShoutNonblockingConn>,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl Future for ShoutAsyncOpen {
+    type Output = Result<ShoutAsyncConn, ShoutConnError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        loop {
+            if poll_sleep(&mut self.sleep, cx).is_pending() {
+                return Poll::Pending;
+            }
+            let state = match self.conn {
+                Some(ref conn) => conn.poll_open(),
+                None => panic!("ShoutAsyncOpen polled after completion"),
+            };
+            match state {
+                Ok(ShoutConnState::Connected) => {
+                    let conn = self.conn.take().unwrap();
+                    return Poll::Ready(Ok(ShoutAsyncConn { conn, sleep: None }));
+                }
+                Ok(_) => self.sleep = Some(Box::pin(time::sleep(POLL_INTERVAL))),
+                Err(e) => return Poll::Ready(Err(e.into())),
+            }
+        }
+    }
+}
+
+/// A connection driven by a Tokio runtime. It implements `AsyncWrite`: after each write, the
+/// next one waits out the `delay()` libshout asks for, in place of the blocking `sync()`.
+pub struct ShoutAsyncConn {
+    conn: ShoutNonblockingConn,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl ShoutAsyncConn {
+    /// Switches an unconnected handle to nonblocking mode and returns a future which connects
+    /// it to the host.
+    pub fn open(conn: ShoutConn) -> Result<ShoutAsyncOpen, ShoutConnError> {
+        Ok(ShoutAsyncOpen {
+            conn: Some(ShoutNonblockingConn::new(conn)?),
+            sleep: None,
+        })
+    }
+
+    /// Returns how long the server wants the caller to wait before sending more data.
+    pub fn delay(&self) -> Duration {
+        self.conn.conn().delay()
+    }
+
+    /// Returns a timer which elapses when the server requires more data.
+    pub fn sync(&self) -> Sleep {
+        time::sleep(self.delay())
+    }
+
+    pub fn conn(&self) -> &ShoutConn {
+        self.conn.conn()
+    }
+
+    pub fn into_inner(self) -> ShoutConn {
+        self.conn.into_inner()
+    }
+}
+
+impl AsyncWrite for ShoutAsyncConn {
+    /// Waits for libshout's write queue to drain before handing it `buf`, which is then queued
+    /// in full.
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        loop {
+            if poll_sleep(&mut self.sleep, cx).is_pending() {
+                return Poll::Pending;
+            }
+            if self.conn.queue_len() > 0 {
+                if self.conn.conn().flush_queue()? > 0 {
+                    self.sleep = Some(Box::pin(time::sleep(POLL_INTERVAL)));
+                }
+                continue;
+            }
+            self.conn.try_send(buf)?;
+            let delay = self.delay();
+            if delay > Duration::from_millis(0) {
+                self.sleep = Some(Box::pin(time::sleep(delay)));
+            }
+            return Poll::Ready(Ok(buf.len()));
+        }
+    }
+
+    /// Waits until libshout's write queue has been handed to the socket.
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        loop {
+            if self.conn.queue_len() == 0 {
+                return Poll::Ready(Ok(()));
+            }
+            if self.sleep.is_none() {
+                if self.conn.conn().flush_queue()? == 0 {
+                    return Poll::Ready(Ok(()));
+                }
+                self.sleep = Some(Box::pin(time::sleep(POLL_INTERVAL)));
+            }
+            if poll_sleep(&mut self.sleep, cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
+    }
+
+    /// Flushes the write queue and disconnects from the host.
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        if self.as_mut().poll_flush(cx)?.is_pending() {
+            return Poll::Pending;
+        }
+        Poll::Ready(self.conn.conn().disconnect().map_err(io::Error::from))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+    use tokio::runtime::{Builder, Runtime};
+    use ShoutErr;
+
+    fn runtime() -> Runtime {
+        Builder::new_current_thread().enable_time().build().unwrap()
+    }
+
+    #[test]
+    fn streams_to_stand_in_server() {
+        let server = ::test_support::StandIn::start();
+        let rt = runtime();
+        let mut conn = rt.block_on(server.builder().build_async().unwrap()).unwrap();
+        assert!(conn.conn().is_connected());
+        rt.block_on(conn.write_all(b"stream data")).unwrap();
+        rt.block_on(conn.shutdown()).unwrap();
+        assert!(!conn.conn().is_connected());
+        assert_eq!(server.finish(), b"stream data");
+    }
+
+    #[test]
+    fn open_fails_without_server() {
+        let _serial = ::test_support::serial();
+        let rt = runtime();
+        match rt.block_on(::test_support::local_builder(::test_support::closed_port()).build_async().unwrap()) {
+            Err(ShoutConnError::ShoutError(e)) => assert_eq!(e.code(), ShoutErr::NoConnect),
+            _ => panic!("expected the connection to be refused"),
+        }
+    }
+}
@@ -1,150 +1,310 @@
 extern crate shout_sys as sys;
+#[cfg(feature = "async")]
+extern crate tokio;
 
-use std::ffi::{CString, NulError};
+#[cfg(feature = "async")]
+mod async_conn;
+#[cfg(feature = "config")]
+mod config;
+mod mp3;
+mod nonblocking;
+mod ogg;
+mod password;
+mod sniff;
+mod split;
+mod stream;
+mod supervisor;
+#[cfg(test)]
+mod test_support;
+mod throttle;
+mod timing;
+mod url;
+mod validate;
+mod writer;
+
+#[cfg(feature = "async")]
+pub use async_conn::{ShoutAsyncConn, ShoutAsyncOpen};
+#[cfg(feature = "config")]
+pub use config::{load_config, parse_config, ShoutConfigError};
+pub use mp3::ShoutMp3Filter;
+pub use nonblocking::{ShoutNonblockingConn, ShoutTrySend};
+pub use ogg::ShoutOggInspector;
+pub use password::ShoutPassword;
+pub use split::{ShoutControl, ShoutSender};
+pub use stream::{ShoutStreamError, ShoutStreamOptions, ShoutStreamProgress, ShoutStreamSummary};
+pub use supervisor::{ShoutBackoff, ShoutSupervisor, ShoutSupervisorEvent};
+pub use throttle::{ShoutMetadataThrottle, ShoutMetadataUpdate};
+pub use timing::{ShoutCancel, ShoutSyncStatus};
+pub use url::ShoutUrlError;
+pub use validate::ShoutValidationError;
+pub use writer::ShoutWriter;
+
+use std::cell::{Cell, RefCell};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::ffi::{CStr, CString, NulError};
+use std::fmt;
+use std::io;
+use std::ops::{BitOr, BitOrAssign};
+use std::os::raw::c_char;
+
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+static GLOBAL_INSTANCE_COUNT: Mutex<usize> = Mutex::new(0);
+
+fn instance_count() -> MutexGuard<'static, usize> {
+    GLOBAL_INSTANCE_COUNT.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Keeps libshout initialized for as long as it is alive. The count is updated under a lock so
+/// `shout_init` always completes before any other thread can create a handle, and
+/// `shout_shutdown` runs only once the last guard is gone.
+struct LibraryGuard;
+
+impl LibraryGuard {
+    fn acquire() -> LibraryGuard {
+        let mut count = instance_count();
+        if *count == 0 {
+            unsafe { sys::shout_init() }
+        }
+        *count += 1;
+        LibraryGuard
+    }
+}
+
+impl Drop for LibraryGuard {
+    fn drop(&mut self) {
+        let mut count = instance_count();
+        *count -= 1;
+        if *count == 0 {
+            unsafe { sys::shout_shutdown() }
+        }
+    }
+}
+
+/// An owned libshout handle, freed when dropped on every path including failed builds.
+struct ShoutHandle {
+    ptr: *mut sys::Shout,
+    // Dropped after `ptr` has been freed.
+    _library: LibraryGuard,
+}
+
+impl ShoutHandle {
+    fn new() -> Result<ShoutHandle, ShoutError> {
+        let library = LibraryGuard::acquire();
+        let ptr = unsafe { sys::shout_new() };
+        if ptr.is_null() {
+            return Err(ShoutError::from_code("new", ShoutErr::Malloc.to_raw()));
+        }
+        Ok(ShoutHandle { ptr, _library: library })
+    }
 
-use std::sync::atomic::{AtomicUsize, Ordering};
-static GLOBAL_INSTANCE_COUNT: AtomicUsize = AtomicUsize::new(0);
+    fn as_ptr(&self) -> *mut sys::Shout {
+        self.ptr
+    }
+}
+
+impl Drop for ShoutHandle {
+    fn drop(&mut self) {
+        unsafe { sys::shout_free(self.ptr) }
+    }
+}
 
 /// Type representing the return of a call to a libshout function.
 /// The Success value should never be returned as an error by this library.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum ShoutErr {
     /// No error
-    Success = 0,
+    Success,
     /// Nonsensical arguments
-    Insane = -1,
+    Insane,
     /// Couldn't connect
-    NoConnect = -2,
+    NoConnect,
     /// Login failed
-    NoLogin = -3,
+    NoLogin,
     /// Socket error
-    Socket = -4,
+    Socket,
     /// Out of memory
-    Malloc = -5,
+    Malloc,
     /// Error updating metadata on the server
-    Metadata = -6,
+    Metadata,
     /// Cannot set parameter while connected
-    Connected = -7,
+    Connected,
     /// Not connected
-    Unconnected = -8,
+    Unconnected,
     /// This libshout version doesn't support the requested operation
-    Unsupported = -9,
+    Unsupported,
     /// The socket is busy
-    Busy = -10,
+    Busy,
     /// TLS requested but not supported by the peer
-    NoTLS = -11,
+    NoTLS,
     /// TLS connection cannot be established due to bad certificate
-    TLSBadCert = -12,
+    TLSBadCert,
     /// Retry last operation
-    Retry = -13,
+    Retry,
+    /// An error code this version of the crate doesn't know about
+    Unknown(i32),
 }
 
-impl From<i32> for ShoutErr {
-    fn from(i: i32) -> Self {
-        ShoutErr::new(i)
+/// Type representing a raw value returned by libshout which doesn't correspond to any known
+/// variant of the enum it was converted into.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ShoutUnknownValue(pub i64);
+
+impl fmt::Display for ShoutUnknownValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown libshout value {}", self.0)
     }
 }
 
-impl ShoutErr {
-    fn new(i: i32) -> ShoutErr {
-        match i {
-            0 => ShoutErr::Success,
-            -1 => ShoutErr::Insane,
-            -2 => ShoutErr::NoConnect,
-            -3 => ShoutErr::NoLogin,
-            -4 => ShoutErr::Socket,
-            -5 => ShoutErr::Malloc,
-            -6 => ShoutErr::Metadata,
-            -7 => ShoutErr::Connected,
-            -8 => ShoutErr::Unconnected,
-            -9 => ShoutErr::Unsupported,
-            -10 => ShoutErr::Busy,
-            -11 => ShoutErr::NoTLS,
-            -12 => ShoutErr::TLSBadCert,
-            -13 => ShoutErr::Retry,
-            _ => unreachable!(),
+impl Error for ShoutUnknownValue {}
+
+/// Generates the conversions between an enum and the raw values libshout uses for it.
+/// `TryFrom` rejects unknown values, while `from_raw` keeps them as `Unknown` so that a
+/// newer or older libshout never causes a panic.
+macro_rules! raw_conversions {
+    ($enum_type:ident, $raw:ty, $(($variant:ident, $val:literal)),+) => (
+        #[allow(deprecated)]
+        impl $enum_type {
+            /// Returns the raw value libshout uses for this variant.
+            pub fn to_raw(&self) -> $raw {
+                match *self {
+                    $( $enum_type::$variant => $val, )+
+                    $enum_type::Unknown(i) => i,
+                }
+            }
+
+            /// Converts a raw value from libshout, keeping values this crate doesn't know
+            /// about as `Unknown`.
+            pub fn from_raw(i: $raw) -> $enum_type {
+                $enum_type::try_from(i).unwrap_or($enum_type::Unknown(i))
+            }
         }
-    }
 
-    pub fn success(&self) -> bool {
-        match *self {
-            ShoutErr::Success => true,
-            _ => false,
+        #[allow(deprecated)]
+        impl TryFrom<$raw> for $enum_type {
+            type Error = ShoutUnknownValue;
+
+            fn try_from(i: $raw) -> Result<Self, Self::Error> {
+                match i {
+                    $( $val => Ok($enum_type::$variant), )+
+                    _ => Err(ShoutUnknownValue(i64::from(i))),
+                }
+            }
         }
+    );
+}
+
+raw_conversions!(ShoutErr, i32,
+                 (Success, 0),
+                 (Insane, -1),
+                 (NoConnect, -2),
+                 (NoLogin, -3),
+                 (Socket, -4),
+                 (Malloc, -5),
+                 (Metadata, -6),
+                 (Connected, -7),
+                 (Unconnected, -8),
+                 (Unsupported, -9),
+                 (Busy, -10),
+                 (NoTLS, -11),
+                 (TLSBadCert, -12),
+                 (Retry, -13));
+
+impl ShoutErr {
+    pub fn success(&self) -> bool {
+        matches!(*self, ShoutErr::Success)
     }
 }
 
+impl fmt::Display for ShoutErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let desc = match *self {
+            ShoutErr::Success => "No error",
+            ShoutErr::Insane => "Nonsensical arguments",
+            ShoutErr::NoConnect => "Couldn't connect",
+            ShoutErr::NoLogin => "Login failed",
+            ShoutErr::Socket => "Socket error",
+            ShoutErr::Malloc => "Out of memory",
+            ShoutErr::Metadata => "Error updating metadata on the server",
+            ShoutErr::Connected => "Cannot set parameter while connected",
+            ShoutErr::Unconnected => "Not connected",
+            ShoutErr::Unsupported => "This libshout version doesn't support the requested operation",
+            ShoutErr::Busy => "The socket is busy",
+            ShoutErr::NoTLS => "TLS requested but not supported by the peer",
+            ShoutErr::TLSBadCert => "TLS connection cannot be established due to bad certificate",
+            ShoutErr::Retry => "Retry last operation",
+            ShoutErr::Unknown(i) => return write!(f, "Unknown error code {}", i),
+        };
+        f.write_str(desc)
+    }
+}
+
+impl Error for ShoutErr {}
+
 /// Type representing a TLS mode to connect to a host with
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum ShoutTLS {
     /// Do not use TLS at all
-    Disabled = 0,
+    Disabled,
     /// Autodetect which TLS mode to use if any.  Please note that this is not a
     /// secure mode as it will *not* prevent any downgrade attacks.
     /// `ShoutTLS::AutoNoPlain` is a more secure version of this mode.
-    Auto = 1,
+    Auto,
     /// TLS (Transport Layer Security) is used. Autodetection is used to find
     /// out about which modes are supported by the server. This mode should be
     /// used for secure connections.
-    AutoNoPlain = 2,
+    AutoNoPlain,
     /// TLS (Transport Layer Security) is used as defined by RFC2818. In this
     /// mode libshout expects a TLS socket on the server side and will begin
     /// with a TLS handshake prior to any other communication.
-    RFC2818 = 11,
+    RFC2818,
     /// TLS (Transport Layer Security) is used as defined by RFC2817. In this
     /// mode libshout will use HTTP/1.1's Upgrade:-process to switch to TLS.
     /// This allows using TLS on a non-TLS socket of the server.
-    RFC2817 = 12,
+    RFC2817,
+    /// A TLS mode this version of the crate doesn't know about
+    Unknown(i32),
 }
 
-impl From<i32> for ShoutTLS {
-    fn from(i: i32) -> Self {
-        match i {
-            0 => ShoutTLS::Disabled,
-            1 => ShoutTLS::Auto,
-            2 => ShoutTLS::AutoNoPlain,
-            11 => ShoutTLS::RFC2818,
-            12 => ShoutTLS::RFC2817,
-            _ => unreachable!(),
-        }
-    }
-}
+raw_conversions!(ShoutTLS, i32,
+                 (Disabled, 0),
+                 (Auto, 1),
+                 (AutoNoPlain, 2),
+                 (RFC2818, 11),
+                 (RFC2817, 12));
 
 /// Type representing the format of data to be streamed to the host is
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum ShoutFormat {
     /// application/ogg
-    Ogg = 0,
+    Ogg,
     /// audio/mpeg
-    MP3 = 1,
+    MP3,
     /// video/webm
-    Webm = 2,
+    Webm,
     #[deprecated(
         since = "0.2.2",
         note = "Please use WebM with ShoutUsage::Audio"
     )]
     /// audio/webm audio only (use Webm with ShoutUsage::Audio)
-    WebmAudio = 3,
-    Matroska = 4,
+    WebmAudio,
+    Matroska,
+    /// A format this version of the crate doesn't know about
+    Unknown(u32),
 }
 
-impl From<u32> for ShoutFormat {
-    fn from(i: u32) -> Self {
-        match i {
-            0 => ShoutFormat::Ogg,
-            1 => ShoutFormat::MP3,
-            2 => ShoutFormat::Webm,
-            #[allow(deprecated)]
-            3 => ShoutFormat::WebmAudio,
-            4 => ShoutFormat::Matroska,
-            _ => unimplemented!(),
-        }
-    }
-}
+raw_conversions!(ShoutFormat, u32,
+                 (Ogg, 0),
+                 (MP3, 1),
+                 (Webm, 2),
+                 (WebmAudio, 3),
+                 (Matroska, 4));
 
-/// Type representing intended usage of the stream.  Internally, the Rust
-/// library uses `Audio` without offering other `ShoutUsage`s to the native
-/// library.
+/// Type representing intended usage of the stream.  Usages are combined into
+/// a `ShoutUsages` set with `|`, e.g. `ShoutUsage::Audio | ShoutUsage::Visual`.
+/// Streams default to `Audio` alone.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum ShoutUsage {
     /// Contains audio substreams
@@ -177,45 +337,163 @@ pub enum ShoutUsage {
     FourD = 0x2000,
 }
 
+impl ShoutUsage {
+    const ALL: [ShoutUsage; 14] = [ShoutUsage::Audio, ShoutUsage::Visual, ShoutUsage::Text,
+                                   ShoutUsage::Subtitle, ShoutUsage::Light, ShoutUsage::Ui,
+                                   ShoutUsage::Metadata, ShoutUsage::Application,
+                                   ShoutUsage::Control, ShoutUsage::Complex, ShoutUsage::Other,
+                                   ShoutUsage::Unknown, ShoutUsage::ThreeD, ShoutUsage::FourD];
+}
+
+/// Type representing a set of `ShoutUsage`s, as passed to libshout's content format.
+#[derive(Copy, Clone, Default, Eq, PartialEq, Hash)]
+pub struct ShoutUsages(u32);
+
+impl ShoutUsages {
+    /// Returns a set containing no usages.
+    pub fn empty() -> ShoutUsages {
+        ShoutUsages(0)
+    }
+
+    /// Builds a set from raw libshout bits, keeping bits this crate doesn't know about.
+    pub fn from_bits(bits: u32) -> ShoutUsages {
+        ShoutUsages(bits)
+    }
+
+    /// Returns the raw bits libshout uses for this set.
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(&self, usage: ShoutUsage) -> bool {
+        self.0 & usage as u32 != 0
+    }
+
+    pub fn insert(&mut self, usage: ShoutUsage) {
+        self.0 |= usage as u32;
+    }
+
+    /// Returns the usages in the set, in the order of their bits.
+    pub fn iter(&self) -> impl Iterator<Item = ShoutUsage> + '_ {
+        ShoutUsage::ALL.iter().cloned().filter(move |&u| self.contains(u))
+    }
+
+    /// Returns the bits which don't correspond to any known `ShoutUsage`.
+    fn unknown_bits(&self) -> u32 {
+        self.0 & !ShoutUsage::ALL.iter().fold(0, |acc, &u| acc | u as u32)
+    }
+}
+
+impl fmt::Debug for ShoutUsages {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut names: Vec<String> = self.iter().map(|u| format!("{:?}", u)).collect();
+        if self.unknown_bits() != 0 {
+            names.push(format!("{:#x}", self.unknown_bits()));
+        }
+        write!(f, "ShoutUsages({})", names.join(" | "))
+    }
+}
+
+impl From<ShoutUsage> for ShoutUsages {
+    fn from(usage: ShoutUsage) -> Self {
+        ShoutUsages(usage as u32)
+    }
+}
+
+impl BitOr for ShoutUsage {
+    type Output = ShoutUsages;
+
+    fn bitor(self, rhs: ShoutUsage) -> ShoutUsages {
+        ShoutUsages(self as u32 | rhs as u32)
+    }
+}
+
+impl BitOr<ShoutUsage> for ShoutUsages {
+    type Output = ShoutUsages;
+
+    fn bitor(self, rhs: ShoutUsage) -> ShoutUsages {
+        ShoutUsages(self.0 | rhs as u32)
+    }
+}
+
+impl BitOr for ShoutUsages {
+    type Output = ShoutUsages;
+
+    fn bitor(self, rhs: ShoutUsages) -> ShoutUsages {
+        ShoutUsages(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign<ShoutUsage> for ShoutUsages {
+    fn bitor_assign(&mut self, rhs: ShoutUsage) {
+        self.insert(rhs);
+    }
+}
+
+impl ShoutFormat {
+    /// Checks a format and usage combination against what libshout accepts, folding the
+    /// deprecated `WebmAudio` into `Webm` with `Audio` usage.
+    fn content_format(self, usage: ShoutUsages) -> Result<(ShoutFormat, ShoutUsages), String> {
+        if usage.is_empty() {
+            return Err(String::from("usage must contain at least one ShoutUsage"));
+        }
+        if usage.unknown_bits() != 0 {
+            return Err(format!("unknown usage bits {:#x}", usage.unknown_bits()));
+        }
+        #[allow(deprecated)]
+        match self {
+            ShoutFormat::WebmAudio if usage != ShoutUsage::Audio.into() => {
+                Err(String::from("WebmAudio only carries audio; use Webm for other usages"))
+            }
+            ShoutFormat::WebmAudio => Ok((ShoutFormat::Webm, usage)),
+            ShoutFormat::MP3 if usage != ShoutUsage::Audio.into() => {
+                Err(format!("MP3 only carries audio, not {:?}", usage))
+            }
+            _ => Ok((self, usage)),
+        }
+    }
+}
+
 /// Type representing the protocol to use for libshout
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum ShoutProtocol {
     /// The HTTP protocol. This is the native protocol of the Icecast 2 server,
     /// and is the default.
-    HTTP = 0,
+    HTTP,
     #[deprecated(
         since = "0.2.2",
         note = "Please use HTTP instead."
     )]
     /// The Audiocast format. This is the native protocol of Icecast 1.
-    XAudioCast = 1,
+    XAudioCast,
     /// The ShoutCast format. This is the native protocol of ShoutCast.
-    Icy = 2,
+    Icy,
     /// The RoarAudio protocol. This is the native protocol for RoarAudio
     /// servers.
-    RoarAudio = 3,
+    RoarAudio,
+    /// A protocol this version of the crate doesn't know about
+    Unknown(u32),
 }
 
-impl From<u32> for ShoutProtocol {
-    fn from(i: u32) -> Self {
-        match i {
-            0 => ShoutProtocol::HTTP,
-            #[allow(deprecated)]
-            1 => ShoutProtocol::XAudioCast,
-            2 => ShoutProtocol::Icy,
-            3 => ShoutProtocol::RoarAudio,
-            _ => unimplemented!(),
-        }
-    }
-}
+raw_conversions!(ShoutProtocol, u32,
+                 (HTTP, 0),
+                 (XAudioCast, 1),
+                 (Icy, 2),
+                 (RoarAudio, 3));
 
-pub static SHOUT_META_NAME: &'static str = "name";
-pub static SHOUT_META_URL: &'static str = "url";
-pub static SHOUT_META_GENRE: &'static str = "genre";
-pub static SHOUT_META_DESCRIPTION: &'static str = "description";
-pub static SHOUT_META_IRC: &'static str = "irc";
-pub static SHOUT_META_AIM: &'static str = "aim";
-pub static SHOUT_META_ICQ: &'static str = "icq";
+pub static SHOUT_META_NAME: &str = "name";
+pub static SHOUT_META_URL: &str = "url";
+pub static SHOUT_META_GENRE: &str = "genre";
+pub static SHOUT_META_DESCRIPTION: &str = "description";
+pub static SHOUT_META_IRC: &str = "irc";
+pub static SHOUT_META_AIM: &str = "aim";
+pub static SHOUT_META_ICQ: &str = "icq";
+pub static SHOUT_META_LOCATION: &str = "location";
+pub static SHOUT_META_LANGUAGE: &str = "language";
 
 /// Type representing a meta value used in setting up the connection with the host.
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -227,36 +505,312 @@ pub enum ShoutMeta {
     IRC(String),
     AIM(String),
     ICQ(String),
+    /// Where the station broadcasts from
+    Location(String),
+    /// Language of the station's programme
+    Language(String),
+    /// Any other station attribute. libshout only accepts keys made of lowercase ASCII
+    /// letters and digits.
+    Custom(String, String),
 }
 
-pub static SHOUT_AI_BITRATE: &'static str = "bitrate";
-pub static SHOUT_AI_SAMPLERATE: &'static str = "samplerate";
-pub static SHOUT_AI_CHANNELS: &'static str = "channels";
-pub static SHOUT_AI_QUALITY: &'static str = "quality";
+/// Type representing the key of a meta value, used to read values back from a connection.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum ShoutMetaKey {
+    Name,
+    Url,
+    Genre,
+    Description,
+    IRC,
+    AIM,
+    ICQ,
+    Location,
+    Language,
+    Custom(String),
+}
+
+impl ShoutMetaKey {
+    /// Returns the key for a raw meta name, using the typed variants where one exists.
+    pub fn from_name(name: &str) -> ShoutMetaKey {
+        match name {
+            "name" => ShoutMetaKey::Name,
+            "url" => ShoutMetaKey::Url,
+            "genre" => ShoutMetaKey::Genre,
+            "description" => ShoutMetaKey::Description,
+            "irc" => ShoutMetaKey::IRC,
+            "aim" => ShoutMetaKey::AIM,
+            "icq" => ShoutMetaKey::ICQ,
+            "location" => ShoutMetaKey::Location,
+            "language" => ShoutMetaKey::Language,
+            _ => ShoutMetaKey::Custom(name.to_owned()),
+        }
+    }
+
+    /// Returns the raw name libshout uses for this key.
+    pub fn as_str(&self) -> &str {
+        match *self {
+            ShoutMetaKey::Name => SHOUT_META_NAME,
+            ShoutMetaKey::Url => SHOUT_META_URL,
+            ShoutMetaKey::Genre => SHOUT_META_GENRE,
+            ShoutMetaKey::Description => SHOUT_META_DESCRIPTION,
+            ShoutMetaKey::IRC => SHOUT_META_IRC,
+            ShoutMetaKey::AIM => SHOUT_META_AIM,
+            ShoutMetaKey::ICQ => SHOUT_META_ICQ,
+            ShoutMetaKey::Location => SHOUT_META_LOCATION,
+            ShoutMetaKey::Language => SHOUT_META_LANGUAGE,
+            ShoutMetaKey::Custom(ref key) => key,
+        }
+    }
+
+    /// Returns whether libshout accepts this key.
+    fn is_valid(&self) -> bool {
+        let key = self.as_str();
+        !key.is_empty() && key.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit())
+    }
+}
+
+impl AsRef<str> for ShoutMetaKey {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl ShoutMeta {
+    /// Builds a meta value from a key and its value.
+    pub fn new(key: ShoutMetaKey, value: String) -> ShoutMeta {
+        match key {
+            ShoutMetaKey::Name => ShoutMeta::Name(value),
+            ShoutMetaKey::Url => ShoutMeta::Url(value),
+            ShoutMetaKey::Genre => ShoutMeta::Genre(value),
+            ShoutMetaKey::Description => ShoutMeta::Description(value),
+            ShoutMetaKey::IRC => ShoutMeta::IRC(value),
+            ShoutMetaKey::AIM => ShoutMeta::AIM(value),
+            ShoutMetaKey::ICQ => ShoutMeta::ICQ(value),
+            ShoutMetaKey::Location => ShoutMeta::Location(value),
+            ShoutMetaKey::Language => ShoutMeta::Language(value),
+            ShoutMetaKey::Custom(key) => ShoutMeta::Custom(key, value),
+        }
+    }
+
+    /// Returns the key this value is set under.
+    pub fn key(&self) -> ShoutMetaKey {
+        match *self {
+            ShoutMeta::Name(_) => ShoutMetaKey::Name,
+            ShoutMeta::Url(_) => ShoutMetaKey::Url,
+            ShoutMeta::Genre(_) => ShoutMetaKey::Genre,
+            ShoutMeta::Description(_) => ShoutMetaKey::Description,
+            ShoutMeta::IRC(_) => ShoutMetaKey::IRC,
+            ShoutMeta::AIM(_) => ShoutMetaKey::AIM,
+            ShoutMeta::ICQ(_) => ShoutMetaKey::ICQ,
+            ShoutMeta::Location(_) => ShoutMetaKey::Location,
+            ShoutMeta::Language(_) => ShoutMetaKey::Language,
+            ShoutMeta::Custom(ref key, _) => ShoutMetaKey::Custom(key.clone()),
+        }
+    }
+
+    fn into_key_value(self) -> (ShoutMetaKey, String) {
+        let key = self.key();
+        match self {
+            ShoutMeta::Name(val) | ShoutMeta::Url(val) | ShoutMeta::Genre(val) |
+            ShoutMeta::Description(val) | ShoutMeta::IRC(val) | ShoutMeta::AIM(val) |
+            ShoutMeta::ICQ(val) | ShoutMeta::Location(val) | ShoutMeta::Language(val) |
+            ShoutMeta::Custom(_, val) => (key, val),
+        }
+    }
+}
+
+pub static SHOUT_AI_BITRATE: &str = "bitrate";
+pub static SHOUT_AI_SAMPLERATE: &str = "samplerate";
+pub static SHOUT_AI_CHANNELS: &str = "channels";
+pub static SHOUT_AI_QUALITY: &str = "quality";
 
 /// Type representing information about the audio data to be sent to the host
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum ShoutAudioInfo {
-    BitRate(String),
-    SampleRate(String),
-    Channels(String),
-    Quality(String),
+    /// Bit rate in kbps
+    BitRate(u32),
+    /// Sample rate in Hz
+    SampleRate(u32),
+    /// Number of channels
+    Channels(u8),
+    /// Encoder quality, e.g. a Vorbis quality level
+    Quality(f32),
+    /// Any other `ice-audio-info` key and its value
+    Custom(String, String),
+}
+
+/// Type representing a failed libshout call. Alongside the returned code it carries the
+/// operation that failed and the error string and errno libshout reported at that point.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ShoutError {
+    operation: &'static str,
+    code: ShoutErr,
+    message: String,
+    errno: i32,
+}
+
+impl ShoutError {
+    /// Captures the error state of `shout` right after `operation` returned `code`.
+    unsafe fn from_shout(shout: *mut sys::Shout, operation: &'static str, code: i32) -> ShoutError {
+        let err = sys::shout_get_error(shout);
+        let message = if err.is_null() {
+            ShoutErr::from_raw(code).to_string()
+        } else {
+            CStr::from_ptr(err).to_string_lossy().into_owned()
+        };
+        ShoutError {
+            operation,
+            code: ShoutErr::from_raw(code),
+            message,
+            errno: sys::shout_get_errno(shout),
+        }
+    }
+
+    /// Builds an error for calls which have no shout handle to query, such as metadata
+    /// manipulation.
+    fn from_code(operation: &'static str, code: i32) -> ShoutError {
+        ShoutError {
+            operation,
+            code: ShoutErr::from_raw(code),
+            message: ShoutErr::from_raw(code).to_string(),
+            errno: code,
+        }
+    }
+
+    /// Builds an error for a call rejected on the Rust side before reaching libshout.
+    fn with_message(operation: &'static str, code: ShoutErr, message: String) -> ShoutError {
+        ShoutError {
+            operation,
+            code,
+            message,
+            errno: code.to_raw(),
+        }
+    }
+
+    /// The libshout operation which failed, e.g. `"open"` or `"set_host"`.
+    pub fn operation(&self) -> &'static str {
+        self.operation
+    }
+
+    /// The code returned by libshout.
+    pub fn code(&self) -> ShoutErr {
+        self.code
+    }
+
+    /// The error string reported by `shout_get_error`.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The error number reported by `shout_get_errno`.
+    pub fn errno(&self) -> i32 {
+        self.errno
+    }
+}
+
+impl fmt::Display for ShoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} failed: {} ({:?}, errno {})", self.operation, self.message, self.code, self.errno)
+    }
+}
+
+impl Error for ShoutError {}
+
+impl From<ShoutError> for io::Error {
+    fn from(err: ShoutError) -> io::Error {
+        let kind = match err.code() {
+            ShoutErr::Insane => io::ErrorKind::InvalidInput,
+            ShoutErr::NoConnect => io::ErrorKind::ConnectionRefused,
+            ShoutErr::NoLogin => io::ErrorKind::PermissionDenied,
+            ShoutErr::Socket => io::ErrorKind::BrokenPipe,
+            ShoutErr::Malloc => io::ErrorKind::OutOfMemory,
+            ShoutErr::Unconnected => io::ErrorKind::NotConnected,
+            ShoutErr::Unsupported => io::ErrorKind::Unsupported,
+            ShoutErr::Busy | ShoutErr::Retry => io::ErrorKind::WouldBlock,
+            _ => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+impl ShoutAudioInfo {
+    /// Interprets a raw audio info pair, as read back from libshout. Values of the known keys
+    /// which don't parse are kept as `Custom`.
+    pub fn from_key_value(key: &str, value: String) -> ShoutAudioInfo {
+        let parsed = match key {
+            "bitrate" => value.parse().ok().map(ShoutAudioInfo::BitRate),
+            "samplerate" => value.parse().ok().map(ShoutAudioInfo::SampleRate),
+            "channels" => value.parse().ok().map(ShoutAudioInfo::Channels),
+            "quality" => value.parse().ok().map(ShoutAudioInfo::Quality),
+            _ => None,
+        };
+        parsed.unwrap_or_else(|| ShoutAudioInfo::Custom(key.to_owned(), value))
+    }
+
+    fn into_key_value(self) -> (String, String) {
+        match self {
+            ShoutAudioInfo::BitRate(val) => (SHOUT_AI_BITRATE.to_owned(), val.to_string()),
+            ShoutAudioInfo::SampleRate(val) => (SHOUT_AI_SAMPLERATE.to_owned(), val.to_string()),
+            ShoutAudioInfo::Channels(val) => (SHOUT_AI_CHANNELS.to_owned(), val.to_string()),
+            ShoutAudioInfo::Quality(val) => (SHOUT_AI_QUALITY.to_owned(), val.to_string()),
+            ShoutAudioInfo::Custom(key, val) => (key, val),
+        }
+    }
 }
 
 /// Type representing an error resulting from either libshout, or processing data to be sent to
 /// libshout
 #[derive(Debug, Eq, PartialEq)]
 pub enum ShoutConnError {
-    ShoutError(ShoutErr),
+    ShoutError(ShoutError),
     NulError(NulError),
 }
 
+impl From<ShoutError> for ShoutConnError {
+    fn from(e: ShoutError) -> Self {
+        ShoutConnError::ShoutError(e)
+    }
+}
+
+impl From<NulError> for ShoutConnError {
+    fn from(e: NulError) -> Self {
+        ShoutConnError::NulError(e)
+    }
+}
+
+impl fmt::Display for ShoutConnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ShoutConnError::ShoutError(ref e) => e.fmt(f),
+            ShoutConnError::NulError(ref e) => write!(f, "invalid string for libshout: {}", e),
+        }
+    }
+}
+
+impl Error for ShoutConnError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            ShoutConnError::ShoutError(ref e) => Some(e),
+            ShoutConnError::NulError(ref e) => Some(e),
+        }
+    }
+}
+
+/// Copies a string owned by libshout, which may be null if the parameter is unset.
+unsafe fn string_from_ptr(ptr: *const c_char) -> Option<String> {
+    if ptr.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(ptr).to_string_lossy().into_owned())
+    }
+}
+
 macro_rules! shout_conn_err {
-    ($func:expr) => (
+    ($shout:expr, $op:expr, $func:expr) => (
         {
             let i = $func;
             if i != 0 {
-                return Err(ShoutConnError::ShoutError(ShoutErr::from(i)));
+                return Err(ShoutError::from_shout($shout, $op, i).into());
             }
         }
     );
@@ -264,8 +818,10 @@ macro_rules! shout_conn_err {
 
 /// A shout connection builder. All desired values should be set in this before
 /// it is built into a `ShoutConn`.  All validation of parameters and FFI calls
-/// happen on build.
-#[derive(Default, Eq, PartialEq)]
+/// happen on `configure` or `build`.
+/// The builder may be cloned to configure several similar mounts, and its `Debug` output
+/// redacts the password.
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct ShoutConnBuilder {
     host: Option<String>,
     port: Option<u16>,
@@ -275,7 +831,7 @@ pub struct ShoutConnBuilder {
     ca_file: Option<String>,
     allowed_ciphers: Option<String>,
     user: Option<String>,
-    password: Option<String>,
+    password: Option<ShoutPassword>,
     client_cert: Option<String>,
     mount: Option<String>,
     dumpfile: Option<String>,
@@ -283,8 +839,10 @@ pub struct ShoutConnBuilder {
     meta: Vec<ShoutMeta>,
     public: Option<u32>,
     format: Option<ShoutFormat>,
+    usage: Option<ShoutUsages>,
     protocol: Option<ShoutProtocol>,
     nonblocking: Option<u32>,
+    detect_format: bool,
 }
 
 impl ShoutConnBuilder {
@@ -302,129 +860,94 @@ impl ShoutConnBuilder {
         self
     }
 
-    pub fn build(self) -> Result<ShoutConn, ShoutConnError> {
-        macro_rules! shout_set_string {
-            ($field:ident, $shout:ident, $func:path) => (
-                {
-                    if let Some(val) = self.$field {
-                        match CString::new(val) {
-                            Ok(cstr) => {
-                                shout_conn_err!($func($shout, cstr.as_ptr()));
-                            }
-                            Err(n) => {
-                                return Err(ShoutConnError::NulError(n));
-                            }
-                        }
-                    }
-                }
-            );
-        }
-
-        macro_rules! shout_set_kv {
-            ($field:ident, $val:ident, $shout:ident, $func:path) => (
-                {
-                    let k = CString::new($field).unwrap();
-                    let v = CString::new($val).unwrap();
-                    shout_conn_err!($func($shout, k.as_ptr(), v.as_ptr()))
-                }
-            );
-        }
-
-        unsafe {
-            let instances = GLOBAL_INSTANCE_COUNT.fetch_add(1, Ordering::SeqCst);
-            if instances == 0 {
-                sys::shout_init();
-            }
-            let shout = sys::shout_new();
-
-            shout_set_string!(host, shout, sys::shout_set_host);
-
-            if let Some(port) = self.port {
-                shout_conn_err!(sys::shout_set_port(shout, port));
-            }
+    /// Sets the password, which may be a `String` or a `ShoutPassword` read from a file or the
+    /// environment.
+    pub fn password<P: Into<ShoutPassword>>(mut self, password: P) -> ShoutConnBuilder {
+        self.password = Some(password.into());
+        self
+    }
 
-            shout_set_string!(agent, shout, sys::shout_set_agent);
+    /// Sets the usage announced along with the format, `Audio` if unset.
+    pub fn usage<U: Into<ShoutUsages>>(mut self, usage: U) -> ShoutConnBuilder {
+        self.usage = Some(usage.into());
+        self
+    }
 
-            if let Some(tls) = self.tls {
-                shout_conn_err!(sys::shout_set_tls(shout, tls as i32));
-            }
+    /// Sets whether to pick the format and usage with `ShoutFormat::detect` from the first chunk
+    /// sent, falling back to the configured ones if it isn't recognised. Until then `build`,
+    /// `ShoutConn::open` and `ShoutConn::reconnect` leave the connection to be opened by the first
+    /// `ShoutConn::send` of a non-empty chunk. If opening fails, both are tried again with the
+    /// next chunk. Nonblocking connections can't detect the format, so `build_nonblocking` and
+    /// `build_async` fail when this is set.
+    pub fn detect_format(mut self, detect_format: bool) -> ShoutConnBuilder {
+        self.detect_format = detect_format;
+        self
+    }
 
-            shout_set_string!(ca_directory, shout, sys::shout_set_ca_directory);
-            shout_set_string!(ca_file, shout, sys::shout_set_ca_file);
-            shout_set_string!(allowed_ciphers, shout, sys::shout_set_allowed_ciphers);
-            shout_set_string!(user, shout, sys::shout_set_user);
-            shout_set_string!(password, shout, sys::shout_set_password);
-            shout_set_string!(client_cert, shout, sys::shout_set_client_certificate);
-            shout_set_string!(mount, shout, sys::shout_set_mount);
-            shout_set_string!(dumpfile, shout, sys::shout_set_dumpfile);
-
-            if let Some(public) = self.public {
-                shout_conn_err!(sys::shout_set_public(shout, public));
-            }
+    /// Creates a libshout handle configured with every parameter set on this builder, without
+    /// connecting. Configuration errors are reported here, separately from network errors,
+    /// which are reported by `ShoutConn::open`.
+    pub fn configure(self) -> Result<ShoutConn, ShoutConnError> {
+        let conn = ShoutConn {
+            shout: ShoutHandle::new()?,
+            detect_format: Cell::new(self.detect_format),
+            audio_info_keys: RefCell::new(Vec::new()),
+            meta_keys: RefCell::new(Vec::new()),
+        };
 
-            if let Some(format) = self.format {
-                #[allow(deprecated)]
-                if format == ShoutFormat::WebmAudio {
-                    shout_conn_err!(sys::shout_set_content_format(shout, ShoutFormat::Webm as u32, ShoutUsage::Audio as u32, std::ptr::null()));
-                } else {
-                    shout_conn_err!(sys::shout_set_content_format(shout, format as u32, ShoutUsage::Audio as u32, std::ptr::null()));
-                }
-            }
+        macro_rules! apply {
+            ($($field:ident => $setter:ident),+) => (
+                $(
+                    if let Some(val) = self.$field {
+                        conn.$setter(val)?;
+                    }
+                )+
+            );
+        }
 
-            if let Some(protocol) = self.protocol {
-                shout_conn_err!(sys::shout_set_protocol(shout, protocol as u32));
-            }
+        apply!(host => set_host,
+               port => set_port,
+               agent => set_agent,
+               tls => set_tls,
+               ca_directory => set_ca_directory,
+               ca_file => set_ca_file,
+               allowed_ciphers => set_allowed_ciphers,
+               user => set_user,
+               password => set_password,
+               client_cert => set_client_cert,
+               mount => set_mount,
+               dumpfile => set_dumpfile,
+               public => set_public,
+               protocol => set_protocol,
+               nonblocking => set_nonblocking);
 
-            if let Some(nonblocking) = self.nonblocking {
-                shout_conn_err!(sys::shout_set_nonblocking(shout, nonblocking));
-            }
+        if self.format.is_some() || self.usage.is_some() {
+            conn.set_content_format(self.format.unwrap_or(ShoutFormat::Ogg),
+                                    self.usage.unwrap_or_else(|| ShoutUsage::Audio.into()))?;
+        }
 
-            for ai in self.audio_info {
-                match ai {
-                    ShoutAudioInfo::BitRate(val) => {
-                        shout_set_kv!(SHOUT_AI_BITRATE, val, shout, sys::shout_set_audio_info);
-                    }
-                    ShoutAudioInfo::SampleRate(val) => {
-                        shout_set_kv!(SHOUT_AI_SAMPLERATE, val, shout, sys::shout_set_audio_info);
-                    }
-                    ShoutAudioInfo::Channels(val) => {
-                        shout_set_kv!(SHOUT_AI_CHANNELS, val, shout, sys::shout_set_audio_info);
-                    }
-                    ShoutAudioInfo::Quality(val) => {
-                        shout_set_kv!(SHOUT_AI_QUALITY, val, shout, sys::shout_set_audio_info);
-                    }
-                }
-            }
+        for ai in self.audio_info {
+            conn.set_audio_info(ai)?;
+        }
+        for meta in self.meta {
+            conn.set_meta(meta)?;
+        }
+        Ok(conn)
+    }
 
-            for meta in self.meta {
-                match meta {
-                    ShoutMeta::Name(val) => {
-                        shout_set_kv!(SHOUT_META_NAME, val, shout, sys::shout_set_meta);
-                    }
-                    ShoutMeta::Url(val) => {
-                        shout_set_kv!(SHOUT_META_URL, val, shout, sys::shout_set_meta);
-                    }
-                    ShoutMeta::Genre(val) => {
-                        shout_set_kv!(SHOUT_META_GENRE, val, shout, sys::shout_set_meta);
-                    }
-                    ShoutMeta::Description(val) => {
-                        shout_set_kv!(SHOUT_META_DESCRIPTION, val, shout, sys::shout_set_meta);
-                    }
-                    ShoutMeta::IRC(val) => {
-                        shout_set_kv!(SHOUT_META_IRC, val, shout, sys::shout_set_meta);
-                    }
-                    ShoutMeta::AIM(val) => {
-                        shout_set_kv!(SHOUT_META_AIM, val, shout, sys::shout_set_meta);
-                    }
-                    ShoutMeta::ICQ(val) => {
-                        shout_set_kv!(SHOUT_META_ICQ, val, shout, sys::shout_set_meta);
-                    }
-                }
-            }
+    /// Configures a libshout handle in nonblocking mode and starts connecting it to the host.
+    /// The connection is completed by polling `ShoutNonblockingConn::poll_open`.
+    pub fn build_nonblocking(self) -> Result<ShoutNonblockingConn, ShoutConnError> {
+        let conn = ShoutNonblockingConn::new(self.configure()?)?;
+        conn.poll_open()?;
+        Ok(conn)
+    }
 
-            shout_conn_err!(sys::shout_open(shout));
-            Ok(ShoutConn { shout: shout })
-        }
+    /// Configures a libshout handle and connects it to the host.
+    pub fn build(self) -> Result<ShoutConn, ShoutConnError> {
+        let conn = self.configure()?;
+        conn.open()?;
+        Ok(conn)
     }
 }
 
@@ -450,7 +973,6 @@ default_build!(ShoutConnBuilder,
                (ca_file, String),
                (allowed_ciphers, String),
                (user, String),
-               (password, String),
                (client_cert, String),
                (mount, String),
                (dumpfile, String),
@@ -459,62 +981,515 @@ default_build!(ShoutConnBuilder,
                (protocol, ShoutProtocol),
                (nonblocking, u32));
 
-/// Struct representing a metadata dict to be used by the shout connection
+/// Records a key set on a handle, keeping the order keys were first set in.
+fn remember<K: PartialEq>(keys: &RefCell<Vec<K>>, key: K) {
+    let mut keys = keys.borrow_mut();
+    if !keys.contains(&key) {
+        keys.push(key);
+    }
+}
+
+/// Reads a little-endian unsigned integer spanning all of `b`.
+fn read_le(b: &[u8]) -> usize {
+    b.iter().rev().fold(0, |n, &b| n << 8 | b as usize)
+}
+
+/// Snapshot of the parameters libshout is using for a connection, after its own defaults have
+/// been applied. The password is redacted: only whether one is set is reported.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShoutSettings {
+    pub host: Option<String>,
+    pub port: u16,
+    pub agent: Option<String>,
+    pub tls: ShoutTLS,
+    pub ca_directory: Option<String>,
+    pub ca_file: Option<String>,
+    pub allowed_ciphers: Option<String>,
+    pub user: Option<String>,
+    pub password_set: bool,
+    pub client_cert: Option<String>,
+    pub mount: Option<String>,
+    pub dumpfile: Option<String>,
+    pub audio_info: Vec<ShoutAudioInfo>,
+    pub meta: Vec<ShoutMeta>,
+    pub public: u32,
+    pub format: ShoutFormat,
+    pub usage: ShoutUsages,
+    pub protocol: ShoutProtocol,
+    pub nonblocking: u32,
+}
+
+/// Struct representing a metadata dict to be used by the shout connection. The entries are kept
+/// on the Rust side and handed to libshout when the metadata is set.
+#[derive(Clone, Default, Eq, PartialEq)]
 pub struct ShoutMetadata {
-    metadata: *mut sys::ShoutMetadata,
+    entries: Vec<(String, String)>,
 }
 
 impl ShoutMetadata {
     pub fn new() -> ShoutMetadata {
-        unsafe { ShoutMetadata { metadata: sys::shout_metadata_new() } }
+        ShoutMetadata { entries: Vec::new() }
+    }
+
+    /// Builds metadata from name and value pairs, failing on the first which contains a NUL
+    /// byte.
+    pub fn from_pairs<I, K, V>(pairs: I) -> Result<ShoutMetadata, ShoutConnError>
+        where I: IntoIterator<Item = (K, V)>,
+              K: Into<String>,
+              V: Into<String>
+    {
+        let mut metadata = ShoutMetadata::new();
+        for (name, value) in pairs {
+            metadata.add(name.into(), value.into())?;
+        }
+        Ok(metadata)
     }
 
-    /// Adds a parameter into the metadata structure.
+    /// Adds a parameter into the metadata structure, replacing any previous value.
     pub fn add(&mut self, name: String, value: String) -> Result<(), ShoutConnError> {
-        match (CString::new(name), CString::new(value)) {
-            (Ok(n), Ok(v)) => {
-                unsafe {
-                    shout_conn_err!(sys::shout_metadata_add(self.metadata, n.as_ptr(), v.as_ptr()));
-                }
-                Ok(())
+        // Checked here so the error surfaces where the bad value is added.
+        CString::new(name.as_str())?;
+        CString::new(value.as_str())?;
+        match self.entries.iter_mut().find(|e| e.0 == name) {
+            Some(entry) => entry.1 = value,
+            None => self.entries.push((name, value)),
+        }
+        Ok(())
+    }
+
+    /// Returns the value of a parameter.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries.iter().find(|e| e.0 == name).map(|e| e.1.as_str())
+    }
+
+    /// Returns the parameters in the order they were first added.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|e| (e.0.as_str(), e.1.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Builds now-playing metadata from the tags of a track, with `song` set to
+    /// "artist - title". Returns `None` if there is neither a title nor an artist.
+    fn from_tags(title: Option<String>, artist: Option<String>, album: Option<String>) -> Option<ShoutMetadata> {
+        let song = match (&title, &artist) {
+            (Some(t), Some(a)) => format!("{} - {}", a, t),
+            (Some(t), None) => t.clone(),
+            (None, Some(a)) => a.clone(),
+            (None, None) => return None,
+        };
+        let mut pairs = vec![("song", song)];
+        pairs.extend(title.map(|t| ("title", t)));
+        pairs.extend(artist.map(|a| ("artist", a)));
+        pairs.extend(album.map(|a| ("album", a)));
+        ShoutMetadata::from_pairs(pairs).ok()
+    }
+
+    /// Copies the entries into a libshout metadata structure.
+    fn to_native(&self) -> Result<NativeMetadata, ShoutConnError> {
+        let native = NativeMetadata(unsafe { sys::shout_metadata_new() });
+        if native.0.is_null() {
+            return Err(ShoutError::from_code("metadata_new", ShoutErr::Malloc.to_raw()).into());
+        }
+        for (name, value) in self.iter() {
+            let (n, v) = (CString::new(name)?, CString::new(value)?);
+            let i = unsafe { sys::shout_metadata_add(native.0, n.as_ptr(), v.as_ptr()) };
+            if i != 0 {
+                return Err(ShoutError::from_code("metadata_add", i).into());
             }
-            (Err(e), _) => Err(ShoutConnError::NulError(e)),
-            (_, Err(e)) => Err(ShoutConnError::NulError(e)),
         }
+        Ok(native)
+    }
+}
+
+macro_rules! metadata_setters {
+    ($(($name:ident, $key:expr)),+) => (
+        impl ShoutMetadata {
+            $(
+                #[doc = concat!("Sets the `", $key, "` parameter.")]
+                pub fn $name(&mut self, value: String) -> Result<(), ShoutConnError> {
+                    self.add(String::from($key), value)
+                }
+            )+
+        }
+    );
+}
+
+metadata_setters!((set_song, "song"),
+                  (set_title, "title"),
+                  (set_artist, "artist"),
+                  (set_url, "url"),
+                  (set_charset, "charset"));
+
+impl fmt::Debug for ShoutMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ShoutMetadata ")?;
+        f.debug_map().entries(self.iter()).finish()
     }
 }
 
-impl Drop for ShoutMetadata {
+/// Now-playing metadata found in a stream while sending it, along with the result of setting it
+/// on the server. The data has been sent either way: Icecast refuses updates for some mounts,
+/// such as Ogg ones, so a failed update shouldn't be mistaken for a failed send.
+#[derive(Debug, Eq, PartialEq)]
+pub struct ShoutFoundMetadata {
+    pub metadata: ShoutMetadata,
+    pub applied: Result<(), ShoutConnError>,
+}
+
+/// A libshout metadata structure, freed when dropped.
+struct NativeMetadata(*mut sys::ShoutMetadata);
+
+impl Drop for NativeMetadata {
     fn drop(&mut self) {
         unsafe {
-            sys::shout_metadata_free(self.metadata);
+            sys::shout_metadata_free(self.0);
         }
     }
 }
 
+/// Type representing the progress of a connection to the host
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ShoutConnState {
+    /// Not connected, and no connection attempt is in progress
+    Unconnected,
+    /// A nonblocking connection attempt is in progress
+    Connecting,
+    /// Connected to the host
+    Connected,
+}
+
 pub struct ShoutConn {
-    shout: *mut sys::Shout,
+    shout: ShoutHandle,
+    /// Whether the first `send` picks the format and connects
+    detect_format: Cell<bool>,
+    /// Audio info and meta keys set on the handle, which libshout has no way to list
+    audio_info_keys: RefCell<Vec<String>>,
+    meta_keys: RefCell<Vec<ShoutMetaKey>>,
 }
 
 impl ShoutConn {
+    /// Connects to the host using the current parameters. In nonblocking mode this waits for
+    /// the connection to complete; use `ShoutNonblockingConn::poll_open` to avoid blocking.
+    /// While the format is still to be detected, connecting is left to the first `send`.
+    pub fn open(&self) -> Result<(), ShoutConnError> {
+        if self.detect_format.get() {
+            return Ok(());
+        }
+        self.connect().map_err(ShoutConnError::from)
+    }
+
+    fn connect(&self) -> Result<(), ShoutError> {
+        let res = unsafe { sys::shout_open(self.shout.as_ptr()) };
+        match ShoutErr::from_raw(res) {
+            ShoutErr::Success => Ok(()),
+            ShoutErr::Busy => loop {
+                thread::sleep(Duration::from_millis(10));
+                match self.state()? {
+                    ShoutConnState::Connected => return Ok(()),
+                    ShoutConnState::Connecting => {}
+                    ShoutConnState::Unconnected => {
+                        return Err(ShoutError::from_code("open", ShoutErr::Unconnected.to_raw()));
+                    }
+                }
+            },
+            _ => Err(unsafe { ShoutError::from_shout(self.shout.as_ptr(), "open", res) }),
+        }
+    }
+
+    /// Returns the state of the connection. For a nonblocking connection in progress this also
+    /// advances it, failing with the error which ended the attempt.
+    pub fn state(&self) -> Result<ShoutConnState, ShoutError> {
+        let res = unsafe { sys::shout_get_connected(self.shout.as_ptr()) };
+        match ShoutErr::from_raw(res) {
+            ShoutErr::Success | ShoutErr::Connected => Ok(ShoutConnState::Connected),
+            ShoutErr::Busy => Ok(ShoutConnState::Connecting),
+            ShoutErr::Unconnected => Ok(ShoutConnState::Unconnected),
+            _ => Err(unsafe { ShoutError::from_shout(self.shout.as_ptr(), "get_connected", res) }),
+        }
+    }
+
+    /// Disconnects from the host. The handle keeps its parameters and may be changed and opened
+    /// again.
+    pub fn disconnect(&self) -> Result<(), ShoutError> {
+        unsafe {
+            shout_conn_err!(self.shout.as_ptr(), "close", sys::shout_close(self.shout.as_ptr()));
+        }
+        Ok(())
+    }
+
+    /// Drains the write queue, disconnects from the host and frees the handle, reporting what
+    /// dropping the connection would silently ignore. In nonblocking mode, draining gives up
+    /// after 10 seconds; see `close_timeout`.
+    pub fn close(self) -> Result<(), ShoutError> {
+        self.close_timeout(Duration::from_secs(10))
+    }
+
+    /// Like `close`, but gives up draining a nonblocking write queue after `timeout`. The
+    /// connection is closed and the handle freed even if draining fails.
+    pub fn close_timeout(self, timeout: Duration) -> Result<(), ShoutError> {
+        if self.state()? != ShoutConnState::Connected {
+            return Ok(());
+        }
+        let drained = self.drain(timeout);
+        let closed = self.disconnect();
+        drained.and(closed)
+    }
+
+    /// Waits until the write queue has been handed to the socket, or `timeout` has passed.
+    fn drain(&self, timeout: Duration) -> Result<(), ShoutError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let queued = self.flush_queue()?;
+            if queued == 0 {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                let message = format!("{} bytes still queued after {:?}", queued, timeout);
+                return Err(ShoutError::with_message("close", ShoutErr::Busy, message));
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Returns whether the handle is currently connected to the host.
+    pub fn is_connected(&self) -> bool {
+        unsafe { sys::shout_get_connected(self.shout.as_ptr()) == ShoutErr::Connected.to_raw() }
+    }
+
+    /// Runs a libshout setter, refusing to change parameters unless the handle is unconnected.
+    fn set<F>(&self, op: &'static str, f: F) -> Result<(), ShoutConnError>
+        where F: FnOnce(*mut sys::Shout) -> i32
+    {
+        let shout = self.shout.as_ptr();
+        unsafe {
+            if sys::shout_get_connected(shout) != ShoutErr::Unconnected.to_raw() {
+                return Err(ShoutError::from_code(op, ShoutErr::Connected.to_raw()).into());
+            }
+            shout_conn_err!(shout, op, f(shout));
+        }
+        Ok(())
+    }
+
+    /// Sets the password. The copy handed to libshout is zeroed once it has been passed on.
+    pub fn set_password<P: Into<ShoutPassword>>(&self, password: P) -> Result<(), ShoutConnError> {
+        let password = password.into();
+        let cstr = match CString::new(password.expose()) {
+            Ok(cstr) => cstr,
+            Err(e) => {
+                // The error carries a copy of the password; wipe it rather than returning it.
+                password::zero(&mut e.into_vec());
+                let message = String::from("password contains a NUL byte");
+                return Err(ShoutError::with_message("set_password", ShoutErr::Insane, message).into());
+            }
+        };
+        let res = self.set("set_password", |shout| unsafe { sys::shout_set_password(shout, cstr.as_ptr()) });
+        password::zero(&mut cstr.into_bytes());
+        res
+    }
+
+    pub fn set_port(&self, port: u16) -> Result<(), ShoutConnError> {
+        self.set("set_port", |shout| unsafe { sys::shout_set_port(shout, port) })
+    }
+
+    pub fn set_tls(&self, tls: ShoutTLS) -> Result<(), ShoutConnError> {
+        self.set("set_tls", |shout| unsafe { sys::shout_set_tls(shout, tls.to_raw()) })
+    }
+
+    pub fn set_public(&self, public: u32) -> Result<(), ShoutConnError> {
+        self.set("set_public", |shout| unsafe { sys::shout_set_public(shout, public) })
+    }
+
+    /// Sets the format of the stream with `Audio` usage.
+    pub fn set_format(&self, format: ShoutFormat) -> Result<(), ShoutConnError> {
+        self.set_content_format(format, ShoutUsage::Audio)
+    }
+
+    /// Sets the format of the stream along with the usages of its substreams, rejecting
+    /// combinations libshout doesn't accept.
+    pub fn set_content_format<U>(&self, format: ShoutFormat, usage: U) -> Result<(), ShoutConnError>
+        where U: Into<ShoutUsages>
+    {
+        let (format, usage) = format.content_format(usage.into())
+            .map_err(|m| ShoutError::with_message("set_content_format", ShoutErr::Insane, m))?;
+        self.set("set_content_format", |shout| unsafe {
+            sys::shout_set_content_format(shout, format.to_raw(), usage.bits(), std::ptr::null())
+        })
+    }
+
+    pub fn set_protocol(&self, protocol: ShoutProtocol) -> Result<(), ShoutConnError> {
+        self.set("set_protocol", |shout| unsafe { sys::shout_set_protocol(shout, protocol.to_raw()) })
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: u32) -> Result<(), ShoutConnError> {
+        self.set("set_nonblocking", |shout| unsafe { sys::shout_set_nonblocking(shout, nonblocking) })
+    }
+
+    pub fn set_audio_info(&self, audio_info: ShoutAudioInfo) -> Result<(), ShoutConnError> {
+        let (k, v) = audio_info.into_key_value();
+        let (ck, cv) = (CString::new(k.as_str())?, CString::new(v)?);
+        self.set("set_audio_info", |shout| unsafe { sys::shout_set_audio_info(shout, ck.as_ptr(), cv.as_ptr()) })?;
+        remember(&self.audio_info_keys, k);
+        Ok(())
+    }
+
+    pub fn set_meta(&self, meta: ShoutMeta) -> Result<(), ShoutConnError> {
+        let (k, v) = meta.into_key_value();
+        if !k.is_valid() {
+            let message = format!("invalid meta key {:?}", k.as_str());
+            return Err(ShoutError::with_message("set_meta", ShoutErr::Insane, message).into());
+        }
+        let (ck, cv) = (CString::new(k.as_str())?, CString::new(v)?);
+        self.set("set_meta", |shout| unsafe { sys::shout_set_meta(shout, ck.as_ptr(), cv.as_ptr()) })?;
+        remember(&self.meta_keys, k);
+        Ok(())
+    }
+
+    pub fn port(&self) -> u16 {
+        unsafe { sys::shout_get_port(self.shout.as_ptr()) }
+    }
+
+    pub fn tls(&self) -> ShoutTLS {
+        ShoutTLS::from_raw(unsafe { sys::shout_get_tls(self.shout.as_ptr()) })
+    }
+
+    pub fn public(&self) -> u32 {
+        unsafe { sys::shout_get_public(self.shout.as_ptr()) }
+    }
+
+    fn content_format(&self) -> (ShoutFormat, ShoutUsages) {
+        let mut format = 0;
+        let mut usage = 0;
+        let mut codecs = std::ptr::null();
+        unsafe {
+            sys::shout_get_content_format(self.shout.as_ptr(), &mut format, &mut usage, &mut codecs);
+        }
+        (ShoutFormat::from_raw(format), ShoutUsages::from_bits(usage))
+    }
+
+    pub fn format(&self) -> ShoutFormat {
+        self.content_format().0
+    }
+
+    pub fn usage(&self) -> ShoutUsages {
+        self.content_format().1
+    }
+
+    pub fn protocol(&self) -> ShoutProtocol {
+        ShoutProtocol::from_raw(unsafe { sys::shout_get_protocol(self.shout.as_ptr()) })
+    }
+
+    pub fn nonblocking(&self) -> u32 {
+        unsafe { sys::shout_get_nonblocking(self.shout.as_ptr()) }
+    }
+
+    /// Returns whether a password has been set, without exposing it.
+    pub fn password_set(&self) -> bool {
+        unsafe { !sys::shout_get_password(self.shout.as_ptr()).is_null() }
+    }
+
+    /// Reads back an audio info value, such as `SHOUT_AI_BITRATE`.
+    pub fn audio_info(&self, key: &str) -> Option<String> {
+        let key = CString::new(key).ok()?;
+        unsafe { string_from_ptr(sys::shout_get_audio_info(self.shout.as_ptr(), key.as_ptr())) }
+    }
+
+    /// Reads back the bit rate in kbps, if set and numeric.
+    pub fn bitrate(&self) -> Option<u32> {
+        self.audio_info(SHOUT_AI_BITRATE).and_then(|v| v.parse().ok())
+    }
+
+    /// Reads back the sample rate in Hz, if set and numeric.
+    pub fn samplerate(&self) -> Option<u32> {
+        self.audio_info(SHOUT_AI_SAMPLERATE).and_then(|v| v.parse().ok())
+    }
+
+    /// Reads back the number of channels, if set and numeric.
+    pub fn channels(&self) -> Option<u8> {
+        self.audio_info(SHOUT_AI_CHANNELS).and_then(|v| v.parse().ok())
+    }
+
+    /// Reads back the encoder quality, if set and numeric.
+    pub fn quality(&self) -> Option<f32> {
+        self.audio_info(SHOUT_AI_QUALITY).and_then(|v| v.parse().ok())
+    }
+
+    /// Reads back a meta value, given either a `ShoutMetaKey` or a raw name such as
+    /// `SHOUT_META_NAME`.
+    pub fn meta<K: AsRef<str>>(&self, key: K) -> Option<String> {
+        let key = CString::new(key.as_ref()).ok()?;
+        unsafe { string_from_ptr(sys::shout_get_meta(self.shout.as_ptr(), key.as_ptr())) }
+    }
+
+    /// Returns a snapshot of every parameter libshout is using, with the password redacted.
+    pub fn settings(&self) -> ShoutSettings {
+        ShoutSettings {
+            host: self.host(),
+            port: self.port(),
+            agent: self.agent(),
+            tls: self.tls(),
+            ca_directory: self.ca_directory(),
+            ca_file: self.ca_file(),
+            allowed_ciphers: self.allowed_ciphers(),
+            user: self.user(),
+            password_set: self.password_set(),
+            client_cert: self.client_cert(),
+            mount: self.mount(),
+            dumpfile: self.dumpfile(),
+            audio_info: self.audio_info_keys.borrow()
+                .iter()
+                .filter_map(|key| self.audio_info(key).map(|v| ShoutAudioInfo::from_key_value(key, v)))
+                .collect(),
+            meta: self.meta_keys.borrow()
+                .iter()
+                .filter_map(|key| self.meta(key).map(|v| ShoutMeta::new(key.clone(), v)))
+                .collect(),
+            public: self.public(),
+            format: self.format(),
+            usage: self.usage(),
+            protocol: self.protocol(),
+            nonblocking: self.nonblocking(),
+        }
+    }
+
     /// Attempts to reconnect to the connection
     pub fn reconnect(&self) -> Result<(), ShoutConnError> {
         unsafe {
-            sys::shout_close(self.shout);
-            shout_conn_err!(sys::shout_open(self.shout));
+            sys::shout_close(self.shout.as_ptr());
         }
-        return Ok(());
+        self.open()
     }
 
     /// Sends data to the server, parsing it for format specific timing info.
-    pub fn send(&self, data: &[u8]) -> Result<(), ShoutErr> {
-        let len = data.len();
-        let res = unsafe { sys::shout_send(self.shout, data.as_ptr() as *const u8, len) };
-        if res == 0 {
-            Ok(())
-        } else {
-            Err(ShoutErr::new(res))
+    pub fn send(&self, data: &[u8]) -> Result<(), ShoutError> {
+        if !data.is_empty() && self.detect_format.get() {
+            if self.state()? == ShoutConnState::Unconnected {
+                self.open_detected(data)?;
+            }
+            self.detect_format.set(false);
+        }
+        unsafe {
+            shout_conn_err!(self.shout.as_ptr(), "send", sys::shout_send(self.shout.as_ptr(), data.as_ptr(), data.len()));
         }
+        Ok(())
+    }
+
+    /// Sends a chunk, unless it is empty, then sets the metadata found in it on the server.
+    /// Only sending can fail the call; the result of setting the metadata is returned with it.
+    fn send_found(&self, data: &[u8], metadata: Option<ShoutMetadata>) -> Result<Option<ShoutFoundMetadata>, ShoutError> {
+        if !data.is_empty() {
+            self.send(data)?;
+        }
+        Ok(metadata.map(|metadata| {
+            let applied = self.set_metadata(&metadata);
+            ShoutFoundMetadata { metadata, applied }
+        }))
     }
 
     #[deprecated(
@@ -523,38 +1498,55 @@ impl ShoutConn {
     )]
     /// Sends unparsed data to the server. Do not use this unless you know what you're doing.
     /// Returns the number of bytes writter, or < 0 on error.
-    pub fn send_raw(&self, data: &[u8]) -> Result<usize, ShoutErr> {
+    pub fn send_raw(&self, data: &[u8]) -> Result<usize, ShoutError> {
         let len = data.len();
         let res = unsafe {
             #[allow(deprecated)]
-            sys::shout_send_raw(self.shout, data.as_ptr() as *const u8, len)
+            sys::shout_send_raw(self.shout.as_ptr(), data.as_ptr(), len)
         };
         if res >= 0 {
             Ok(res as usize)
         } else {
-            Err(ShoutErr::new(res as i32))
+            Err(unsafe { ShoutError::from_shout(self.shout.as_ptr(), "send_raw", res as i32) })
+        }
+    }
+
+    /// Asks libshout to hand more of its write queue to the socket, returning how many bytes are
+    /// left on it. A busy socket isn't an error.
+    fn flush_queue(&self) -> Result<usize, ShoutError> {
+        // Not through `send`, which could open a connection waiting for its first chunk.
+        let shout = self.shout.as_ptr();
+        let res = unsafe { sys::shout_send(shout, [].as_ptr(), 0) };
+        if res != 0 {
+            let err = unsafe { ShoutError::from_shout(shout, "send", res) };
+            if err.code() != ShoutErr::Busy && err.code() != ShoutErr::Retry {
+                return Err(err);
+            }
         }
+        Ok(self.queue_len().max(0) as usize)
     }
 
     /// Returns the number of bytes on the write queue. Only makes sense in nonblocking mode.
     pub fn queue_len(&self) -> isize {
-        unsafe { sys::shout_queuelen(self.shout) }
+        unsafe { sys::shout_queuelen(self.shout.as_ptr()) }
     }
 
     /// Sleeps the thread until the server requires more data
     pub fn sync(&self) {
-        unsafe { sys::shout_sync(self.shout) }
+        unsafe { sys::shout_sync(self.shout.as_ptr()) }
     }
 
     /// Returns the amount of time the caller should wait before sending more data
-    pub fn delay(&self) -> i32 {
-        unsafe { sys::shout_delay(self.shout) }
+    pub fn delay(&self) -> Duration {
+        let ms = unsafe { sys::shout_delay(self.shout.as_ptr()) };
+        Duration::from_millis(ms.max(0) as u64)
     }
 
     /// Sets metadata for the host
-    pub fn set_metadata(&self, metadata: ShoutMetadata) -> Result<(), ShoutConnError> {
+    pub fn set_metadata(&self, metadata: &ShoutMetadata) -> Result<(), ShoutConnError> {
+        let native = metadata.to_native()?;
         unsafe {
-            shout_conn_err!(sys::shout_set_metadata(self.shout, metadata.metadata));
+            shout_conn_err!(self.shout.as_ptr(), "set_metadata", sys::shout_set_metadata(self.shout.as_ptr(), native.0));
             Ok(())
         }
     }
@@ -563,23 +1555,69 @@ impl ShoutConn {
 impl Drop for ShoutConn {
     fn drop(&mut self) {
         unsafe {
-            sys::shout_close(self.shout);
-            sys::shout_free(self.shout);
-            let instances = GLOBAL_INSTANCE_COUNT.fetch_sub(1, Ordering::SeqCst);
-            if instances == 1 {
-                sys::shout_shutdown();
-            }
+            sys::shout_close(self.shout.as_ptr());
         }
     }
 }
 
+macro_rules! conn_set_string {
+    ($(($name:ident, $func:path)),+) => (
+        impl ShoutConn {
+            $(
+                /// Sets the corresponding libshout parameter. Fails with `ShoutErr::Connected`
+                /// unless the handle is unconnected.
+                pub fn $name(&self, val: String) -> Result<(), ShoutConnError> {
+                    let cstr = CString::new(val)?;
+                    self.set(stringify!($name), |shout| unsafe { $func(shout, cstr.as_ptr()) })
+                }
+            )+
+        }
+    );
+}
+
+conn_set_string!((set_host, sys::shout_set_host),
+                 (set_agent, sys::shout_set_agent),
+                 (set_ca_directory, sys::shout_set_ca_directory),
+                 (set_ca_file, sys::shout_set_ca_file),
+                 (set_allowed_ciphers, sys::shout_set_allowed_ciphers),
+                 (set_user, sys::shout_set_user),
+                 (set_client_cert, sys::shout_set_client_certificate),
+                 (set_mount, sys::shout_set_mount),
+                 (set_dumpfile, sys::shout_set_dumpfile));
+
+macro_rules! conn_get_string {
+    ($(($name:ident, $func:path)),+) => (
+        impl ShoutConn {
+            $(
+                /// Returns the value libshout is using for the corresponding parameter.
+                pub fn $name(&self) -> Option<String> {
+                    unsafe { string_from_ptr($func(self.shout.as_ptr())) }
+                }
+            )+
+        }
+    );
+}
+
+conn_get_string!((host, sys::shout_get_host),
+                 (agent, sys::shout_get_agent),
+                 (ca_directory, sys::shout_get_ca_directory),
+                 (ca_file, sys::shout_get_ca_file),
+                 (allowed_ciphers, sys::shout_get_allowed_ciphers),
+                 (user, sys::shout_get_user),
+                 (client_cert, sys::shout_get_client_certificate),
+                 (mount, sys::shout_get_mount),
+                 (dumpfile, sys::shout_get_dumpfile));
+
 unsafe impl Send for ShoutConn { }
 
 #[cfg(test)]
 mod tests {
-    use super::sys;
+    use super::*;
+    use test_support::{closed_port, local_builder, serial, StandIn};
+
     #[test]
     fn it_works() {
+        let _serial = serial();
         unsafe {
             sys::shout_init();
             let s = sys::shout_new();
@@ -587,4 +1625,227 @@ mod tests {
             sys::shout_shutdown();
         }
     }
+
+    #[test]
+    fn failed_open_reports_operation_and_message() {
+        let _serial = serial();
+        let res = ShoutConnBuilder::new()
+            .host(String::from("127.0.0.1"))
+            .port(closed_port())
+            .password(String::from("pw"))
+            .mount(String::from("/test.ogg"))
+            .build();
+        match res {
+            Err(ShoutConnError::ShoutError(e)) => {
+                assert_eq!(e.operation(), "open");
+                assert_eq!(e.code(), ShoutErr::NoConnect);
+                assert!(!e.message().is_empty());
+                assert!(e.to_string().starts_with("open failed: "));
+            }
+            _ => panic!("expected a connection failure"),
+        }
+    }
+
+    #[test]
+    fn unknown_raw_values_do_not_panic() {
+        assert_eq!(ShoutErr::from_raw(-13), ShoutErr::Retry);
+        assert_eq!(ShoutErr::from_raw(-99), ShoutErr::Unknown(-99));
+        assert_eq!(ShoutErr::try_from(-99), Err(ShoutUnknownValue(-99)));
+        assert_eq!(ShoutTLS::from_raw(7), ShoutTLS::Unknown(7));
+        assert_eq!(ShoutFormat::try_from(4), Ok(ShoutFormat::Matroska));
+        assert_eq!(ShoutFormat::from_raw(42).to_raw(), 42);
+        assert_eq!(ShoutProtocol::from_raw(9), ShoutProtocol::Unknown(9));
+    }
+
+    #[test]
+    fn failed_builds_release_the_library() {
+        let _serial = serial();
+        for _ in 0..3 {
+            let res = ShoutConnBuilder::new()
+                .host(String::from("127.0.0.1"))
+                .port(closed_port())
+                .password(String::from("pw"))
+                .mount(String::from("/test.ogg"))
+                .build();
+            assert!(res.is_err());
+            assert_eq!(*instance_count(), 0);
+
+            let res = ShoutConnBuilder::new().host(String::from("bad\0host")).build();
+            match res {
+                Err(ShoutConnError::NulError(_)) => {}
+                _ => panic!("expected a NulError"),
+            }
+            assert_eq!(*instance_count(), 0);
+        }
+    }
+
+    #[test]
+    fn close_drains_and_frees() {
+        let server = StandIn::start();
+        let conn = local_builder(closed_port()).configure().unwrap();
+        conn.close().unwrap();
+        assert_eq!(*instance_count(), 0);
+
+        let conn = server.builder().nonblocking(1).build().unwrap();
+        conn.send(b"last data").unwrap();
+        conn.close().unwrap();
+        assert_eq!(*instance_count(), 0);
+        assert_eq!(server.finish(), b"last data");
+    }
+
+    #[test]
+    fn metadata_mirrors_entries() {
+        let mut metadata = ShoutMetadata::from_pairs(vec![("song", "First"), ("charset", "UTF-8")]).unwrap();
+        metadata.set_song(String::from("Second")).unwrap();
+        metadata.set_artist(String::from("Artist")).unwrap();
+        assert_eq!(metadata.get("song"), Some("Second"));
+        assert_eq!(metadata.len(), 3);
+        assert_eq!(format!("{:?}", metadata),
+                   r#"ShoutMetadata {"song": "Second", "charset": "UTF-8", "artist": "Artist"}"#);
+        assert_eq!(metadata.clone(), metadata);
+        assert!(metadata.set_title(String::from("bad\0title")).is_err());
+        assert_eq!(metadata.get("title"), None);
+
+        let _serial = serial();
+        let conn = local_builder(closed_port()).configure().unwrap();
+        for _ in 0..2 {
+            match conn.set_metadata(&metadata) {
+                Err(ShoutConnError::ShoutError(e)) => assert_eq!(e.code(), ShoutErr::Unconnected),
+                _ => panic!("expected metadata to need a connection"),
+            }
+        }
+    }
+
+    #[test]
+    fn configure_does_not_connect() {
+        let _serial = serial();
+        let conn = ShoutConnBuilder::new()
+            .host(String::from("127.0.0.1"))
+            .port(closed_port())
+            .password(String::from("pw"))
+            .configure()
+            .unwrap();
+        assert!(!conn.is_connected());
+        conn.set_mount(String::from("/changed.ogg")).unwrap();
+        assert_eq!(conn.disconnect().unwrap_err().code(), ShoutErr::Unconnected);
+        match conn.open() {
+            Err(ShoutConnError::ShoutError(e)) => assert_eq!(e.code(), ShoutErr::NoConnect),
+            _ => panic!("expected a connection failure"),
+        }
+    }
+
+    #[test]
+    fn settings_reflect_configuration() {
+        let _serial = serial();
+        let conn = ShoutConnBuilder::new()
+            .host(String::from("127.0.0.1"))
+            .port(8123)
+            .password(String::from("secret"))
+            .mount(String::from("/live.mp3"))
+            .format(ShoutFormat::MP3)
+            .add_audio_info(ShoutAudioInfo::BitRate(128))
+            .add_audio_info(ShoutAudioInfo::Quality(0.5))
+            .add_audio_info(ShoutAudioInfo::Custom(String::from("ice-foo"), String::from("bar")))
+            .add_meta(ShoutMeta::Name(String::from("Test radio")))
+            .add_meta(ShoutMeta::Location(String::from("Berlin")))
+            .configure()
+            .unwrap();
+        let settings = conn.settings();
+        assert_eq!(settings.host, Some(String::from("127.0.0.1")));
+        assert_eq!(settings.port, 8123);
+        assert_eq!(settings.mount, Some(String::from("/live.mp3")));
+        assert_eq!(settings.format, ShoutFormat::MP3);
+        assert!(settings.password_set);
+        assert_eq!(settings.audio_info, vec![
+            ShoutAudioInfo::BitRate(128),
+            ShoutAudioInfo::Quality(0.5),
+            ShoutAudioInfo::Custom(String::from("ice-foo"), String::from("bar")),
+        ]);
+        assert_eq!(conn.bitrate(), Some(128));
+        assert_eq!(conn.quality(), Some(0.5));
+        assert_eq!(conn.channels(), None);
+        assert_eq!(conn.audio_info("ice-foo"), Some(String::from("bar")));
+        assert_eq!(settings.meta, vec![
+            ShoutMeta::Name(String::from("Test radio")),
+            ShoutMeta::Location(String::from("Berlin")),
+        ]);
+        assert_eq!(conn.meta(ShoutMetaKey::Name), Some(String::from("Test radio")));
+        assert_eq!(conn.meta(ShoutMetaKey::from_name("location")), Some(String::from("Berlin")));
+        assert_eq!(conn.meta("genre"), None);
+        assert!(conn.set_meta(ShoutMeta::Custom(String::from("Bad Key"), String::new())).is_err());
+        assert!(!format!("{:?}", settings).contains("secret"));
+    }
+
+    #[test]
+    fn meta_keys_round_trip() {
+        let keys = vec![
+            ShoutMetaKey::Name,
+            ShoutMetaKey::Url,
+            ShoutMetaKey::Genre,
+            ShoutMetaKey::Description,
+            ShoutMetaKey::IRC,
+            ShoutMetaKey::AIM,
+            ShoutMetaKey::ICQ,
+            ShoutMetaKey::Location,
+            ShoutMetaKey::Language,
+            ShoutMetaKey::Custom(String::from("city")),
+        ];
+        for key in keys {
+            assert_eq!(ShoutMetaKey::from_name(key.as_str()), key);
+            assert_eq!(ShoutMeta::new(key.clone(), String::from("x")).key(), key);
+        }
+        assert_eq!(ShoutMetaKey::from_name("language"), ShoutMetaKey::Language);
+        assert_eq!(ShoutMetaKey::Location.as_str(), "location");
+    }
+
+    #[test]
+    fn set_password_redacts_nul_error() {
+        let _serial = serial();
+        let conn = ShoutConnBuilder::new().configure().unwrap();
+        let err = conn.set_password(String::from("hunter2\0")).unwrap_err();
+        match err {
+            ShoutConnError::ShoutError(ref e) => {
+                assert_eq!(e.operation(), "set_password");
+                assert_eq!(e.code(), ShoutErr::Insane);
+            }
+            ref e => panic!("unexpected error: {:?}", e),
+        }
+        assert!(!format!("{:?}", err).contains("hunter2"));
+        assert!(!err.to_string().contains("hunter2"));
+    }
+
+    #[test]
+    fn builder_debug_redacts_password() {
+        let builder = ShoutConnBuilder::new()
+            .host(String::from("localhost"))
+            .password(String::from("hunter2"));
+        let copy = builder.clone().mount(String::from("/other.ogg"));
+        let debug = format!("{:?}", copy);
+        assert!(debug.contains("localhost"));
+        assert!(debug.contains("/other.ogg"));
+        assert!(!debug.contains("hunter2"));
+        assert_eq!(builder.password.as_ref().map(ShoutPassword::expose), Some("hunter2"));
+    }
+
+    #[test]
+    fn content_format_combinations() {
+        let av = ShoutUsage::Audio | ShoutUsage::Visual;
+        assert_eq!(format!("{:?}", av), "ShoutUsages(Audio | Visual)");
+        assert_eq!(ShoutFormat::Webm.content_format(av), Ok((ShoutFormat::Webm, av)));
+        #[allow(deprecated)]
+        let webm_audio = ShoutFormat::WebmAudio.content_format(ShoutUsage::Audio.into());
+        assert_eq!(webm_audio, Ok((ShoutFormat::Webm, ShoutUsage::Audio.into())));
+        assert!(ShoutFormat::MP3.content_format(av).is_err());
+        assert!(ShoutFormat::Ogg.content_format(ShoutUsages::empty()).is_err());
+        assert!(ShoutFormat::Ogg.content_format(ShoutUsages::from_bits(0x8000)).is_err());
+
+        let _serial = serial();
+        let conn = ShoutConnBuilder::new()
+            .format(ShoutFormat::Matroska)
+            .usage(ShoutUsage::Audio | ShoutUsage::Subtitle)
+            .configure()
+            .unwrap();
+        assert_eq!(conn.format(), ShoutFormat::Matroska);
+        assert_eq!(conn.usage(), ShoutUsage::Audio | ShoutUsage::Subtitle);
+    }
 }
@@ -0,0 +1,435 @@
+//! Stripping ID3 and APE tags from MP3 streams, and reading now-playing metadata from ID3v2.
+
+use {read_le, ShoutConn, ShoutError, ShoutFoundMetadata, ShoutMetadata};
+
+/// ID3v2 tags are only buffered up to this size for reading frames; the rest of the tag, normally
+/// embedded cover art, is dropped unread.
+const MAX_TAG_LEN: usize = 64 * 1024;
+
+/// How many bytes which are neither frames nor tags are held back in case an APE tag footer
+/// follows, which would mark them as the tag's items.
+const MAX_APE_LEN: usize = 64 * 1024;
+
+/// What the bytes at the current position of the stream are
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Chunk {
+    /// More bytes are needed to tell
+    Incomplete,
+    /// An MPEG audio frame of the given length
+    Frame(usize),
+    /// An ID3v2 tag of the given length
+    Id3v2(usize),
+    /// An ID3v1 or APE tag of the given length, headers and footers included
+    Tag(usize),
+    /// The footer of an APE tag without a header, whose items take the given number of bytes
+    /// before it
+    ApeFooter(usize),
+    /// Anything else
+    Unknown,
+}
+
+/// Returns the length of the MPEG audio frame with the given header, or `None` if it isn't a
+/// valid header.
+pub(crate) fn frame_len(h: &[u8]) -> Option<usize> {
+    const BITRATES: [[u32; 14]; 5] = [
+        [32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448],
+        [32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384],
+        [32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320],
+        [32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256],
+        [8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+    ];
+    if h[0] != 0xFF || h[1] & 0xE0 != 0xE0 {
+        return None;
+    }
+    let version = (h[1] >> 3) & 3;
+    let layer = (h[1] >> 1) & 3;
+    let bitrate = (h[2] >> 4) as usize;
+    let rate = ((h[2] >> 2) & 3) as usize;
+    let padding = ((h[2] >> 1) & 1) as u32;
+    // Free format frames have no length in the header.
+    if version == 1 || layer == 0 || bitrate == 0 || bitrate == 15 || rate == 3 {
+        return None;
+    }
+    let mpeg1 = version == 3;
+    let table = match (mpeg1, layer) {
+        (true, 3) => 0,
+        (true, 2) => 1,
+        (true, _) => 2,
+        (false, 3) => 3,
+        (false, _) => 4,
+    };
+    let bitrate = BITRATES[table][bitrate - 1] * 1000;
+    let sample_rate = [44100, 48000, 32000][rate] >> (3 - version).min(2);
+    let len = match layer {
+        3 => (12 * bitrate / sample_rate + padding) * 4,
+        1 if !mpeg1 => 72 * bitrate / sample_rate + padding,
+        _ => 144 * bitrate / sample_rate + padding,
+    };
+    Some(len as usize)
+}
+
+/// Decodes an ID3v2 syncsafe integer.
+fn syncsafe(b: &[u8]) -> usize {
+    b.iter().fold(0, |n, &b| n << 7 | (b & 0x7F) as usize)
+}
+
+fn read_be(b: &[u8]) -> usize {
+    b.iter().fold(0, |n, &b| n << 8 | b as usize)
+}
+
+/// Tells what the bytes at the start of `data` are.
+fn classify(data: &[u8]) -> Chunk {
+    // Waits for enough bytes to compare against `magic`, returning whether they match.
+    macro_rules! magic {
+        ($magic:expr, $len:expr) => ({
+            let magic: &[u8] = $magic;
+            let n = data.len().min(magic.len());
+            if data[..n] == magic[..n] && data.len() < $len {
+                return Chunk::Incomplete;
+            }
+            data.starts_with(magic)
+        });
+    }
+
+    if magic!(b"ID3", 10) {
+        let footer = if data[3] >= 4 && data[5] & 0x10 != 0 { 10 } else { 0 };
+        return Chunk::Id3v2(10 + syncsafe(&data[6..10]) + footer);
+    }
+    if magic!(b"TAG+", 4) {
+        return Chunk::Tag(227);
+    }
+    if magic!(b"TAG", 3) {
+        return Chunk::Tag(128);
+    }
+    if magic!(b"APETAGEX", 32) {
+        let size = read_le(&data[12..16]);
+        let flags = read_le(&data[20..24]);
+        return if flags & (1 << 29) != 0 {
+            Chunk::Tag(32 + size)
+        } else {
+            Chunk::ApeFooter(size.saturating_sub(32))
+        };
+    }
+    if data[0] == 0xFF && data.len() < 4 {
+        return Chunk::Incomplete;
+    }
+    match frame_len(data) {
+        Some(len) => Chunk::Frame(len),
+        None => Chunk::Unknown,
+    }
+}
+
+/// Decodes the text of an ID3v2 text frame, up to its first value.
+fn decode_text(data: &[u8]) -> Option<String> {
+    let (&encoding, text) = data.split_first()?;
+    let text = match encoding {
+        0 => text.iter().map(|&b| b as char).collect(),
+        1 | 2 => {
+            let mut units: Vec<u16> = text.chunks(2).filter(|c| c.len() == 2).map(|c| u16::from(c[0]) << 8 | u16::from(c[1])).collect();
+            let mut big_endian = encoding == 2;
+            match units.first() {
+                Some(&0xFEFF) => { units.remove(0); big_endian = true; }
+                Some(&0xFFFE) => { units.remove(0); big_endian = false; }
+                _ => {}
+            }
+            if !big_endian {
+                units = units.iter().map(|u| u.swap_bytes()).collect();
+            }
+            String::from_utf16_lossy(&units)
+        }
+        3 => String::from_utf8_lossy(text).into_owned(),
+        _ => return None,
+    };
+    let text = text.split('\0').next().unwrap_or("").trim().to_owned();
+    if text.is_empty() { None } else { Some(text) }
+}
+
+/// Reads TIT2, TPE1 and TALB, or their ID3v2.2 equivalents, from a possibly cut off tag.
+fn parse_id3v2(tag: &[u8]) -> Option<ShoutMetadata> {
+    if tag.len() < 10 {
+        return None;
+    }
+    let version = tag[3];
+    let flags = tag[5];
+    let mut body = tag[10..].to_vec();
+    if flags & 0x80 != 0 && version < 4 {
+        // Undo unsynchronisation, which inserts a zero after every 0xFF.
+        let mut prev = 0;
+        body.retain(|&b| {
+            let keep = !(prev == 0xFF && b == 0);
+            prev = b;
+            keep
+        });
+    }
+    let mut pos = 0;
+    if flags & 0x40 != 0 && body.len() >= 4 {
+        pos = match version {
+            3 => 4 + read_be(&body[..4]),
+            4 => syncsafe(&body[..4]),
+            _ => 0,
+        };
+    }
+
+    let (id_len, header_len) = if version == 2 { (3, 6) } else { (4, 10) };
+    let (mut title, mut artist, mut album) = (None, None, None);
+    while pos + header_len <= body.len() && body[pos] != 0 {
+        let header = &body[pos..pos + header_len];
+        let size = match version {
+            2 => read_be(&header[3..6]),
+            3 => read_be(&header[4..8]),
+            _ => syncsafe(&header[4..8]),
+        };
+        let start = pos + header_len;
+        let end = start.saturating_add(size).min(body.len());
+        pos = start.saturating_add(size);
+        let mut data = &body[start..end];
+        if version == 3 && header[9] & 0xC0 != 0 || version == 4 && header[9] & 0x0C != 0 {
+            // Compressed or encrypted
+            continue;
+        }
+        if version == 4 && header[9] & 0x01 != 0 {
+            data = data.get(4..).unwrap_or(&[]);
+        }
+        match &header[..id_len] {
+            b"TIT2" | b"TT2" => title = decode_text(data),
+            b"TPE1" | b"TP1" => artist = decode_text(data),
+            b"TALB" | b"TAL" => album = decode_text(data),
+            _ => {}
+        }
+    }
+
+    ShoutMetadata::from_tags(title, artist, album)
+}
+
+/// Removes ID3v1, ID3v2 and APE tags from an MP3 stream so they don't reach listeners as noise,
+/// and reads the title, artist and album from ID3v2 tags. The stream is followed frame by frame,
+/// so data may be fed in chunks of any size; a few bytes are held back until the next chunk
+/// shows what they are, and are released by `finish`.
+#[derive(Default)]
+pub struct ShoutMp3Filter {
+    buf: Vec<u8>,
+    /// Bytes of the current frame still to be passed through
+    pass: usize,
+    /// Bytes of the current tag still to be dropped
+    skip: usize,
+    /// The start of the ID3v2 tag being dropped, kept for reading its frames
+    tag: Option<Vec<u8>>,
+    /// Bytes which are neither frames nor tags, held back in case they're APE tag items
+    unknown: Vec<u8>,
+}
+
+impl ShoutMp3Filter {
+    pub fn new() -> ShoutMp3Filter {
+        ShoutMp3Filter::default()
+    }
+
+    /// Appends the next chunk of the stream to `out` without its tags, returning the metadata
+    /// of the last ID3v2 tag completed by it.
+    pub fn filter(&mut self, data: &[u8], out: &mut Vec<u8>) -> Option<ShoutMetadata> {
+        self.buf.extend_from_slice(data);
+        let mut metadata = None;
+        let mut pos = 0;
+        loop {
+            let rest = &self.buf[pos..];
+            if self.pass > 0 {
+                let n = self.pass.min(rest.len());
+                out.extend_from_slice(&rest[..n]);
+                self.pass -= n;
+                pos += n;
+                if self.pass > 0 {
+                    break;
+                }
+                continue;
+            }
+            if self.skip > 0 {
+                let n = self.skip.min(rest.len());
+                if let Some(ref mut tag) = self.tag {
+                    let room = MAX_TAG_LEN.saturating_sub(tag.len());
+                    tag.extend_from_slice(&rest[..n.min(room)]);
+                }
+                self.skip -= n;
+                pos += n;
+                if self.skip > 0 {
+                    break;
+                }
+                if let Some(tag) = self.tag.take() {
+                    metadata = parse_id3v2(&tag).or(metadata);
+                }
+                continue;
+            }
+            if rest.is_empty() {
+                break;
+            }
+            match classify(rest) {
+                Chunk::Incomplete => break,
+                Chunk::Unknown => {
+                    self.unknown.push(rest[0]);
+                    pos += 1;
+                    if self.unknown.len() > MAX_APE_LEN {
+                        out.append(&mut self.unknown);
+                    }
+                    continue;
+                }
+                Chunk::ApeFooter(items) => {
+                    let keep = self.unknown.len().saturating_sub(items);
+                    self.unknown.truncate(keep);
+                    self.skip = 32;
+                }
+                Chunk::Frame(len) => self.pass = len,
+                Chunk::Id3v2(len) => {
+                    self.skip = len;
+                    self.tag = Some(Vec::new());
+                }
+                Chunk::Tag(len) => self.skip = len,
+            }
+            out.append(&mut self.unknown);
+        }
+        self.buf.drain(..pos);
+        metadata
+    }
+
+    /// Appends the bytes held back at the end of the stream to `out`.
+    pub fn finish(&mut self, out: &mut Vec<u8>) {
+        out.append(&mut self.unknown);
+        if self.skip == 0 {
+            out.append(&mut self.buf);
+        }
+        *self = ShoutMp3Filter::new();
+    }
+
+    /// Filters the chunk and sends what is left of it, then sets any metadata found on the
+    /// server.
+    pub fn send(&mut self, conn: &ShoutConn, data: &[u8]) -> Result<Option<ShoutFoundMetadata>, ShoutError> {
+        let mut out = Vec::with_capacity(data.len());
+        let metadata = self.filter(data, &mut out);
+        conn.send_found(&out, metadata)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An MPEG-1 Layer III frame at 128 kbps and 44.1 kHz, 417 bytes long
+    fn frame(fill: u8) -> Vec<u8> {
+        let mut frame = vec![0xFF, 0xFB, 0x90, 0x00];
+        frame.resize(417, fill);
+        frame
+    }
+
+    fn id3v2(frames: &[(&[u8], &[u8])]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for &(id, text) in frames {
+            body.extend_from_slice(id);
+            body.extend_from_slice(&((text.len() + 1) as u32).to_be_bytes());
+            body.extend_from_slice(&[0, 0, 3]);
+            body.extend_from_slice(text);
+        }
+        body.resize(body.len() + 20, 0);
+        let size = body.len();
+        let mut tag = b"ID3\x03\x00\x00".to_vec();
+        tag.extend((0..4).rev().map(|i| (size >> (7 * i)) as u8 & 0x7F));
+        tag.extend(body);
+        tag
+    }
+
+    fn ape(header: bool) -> Vec<u8> {
+        let items = b"\x05\0\0\0\0\0\0\0Title\0Hello";
+        let block = |flags: u32| {
+            let mut b = b"APETAGEX".to_vec();
+            b.extend_from_slice(&2000u32.to_le_bytes());
+            b.extend_from_slice(&(items.len() as u32 + 32).to_le_bytes());
+            b.extend_from_slice(&1u32.to_le_bytes());
+            b.extend_from_slice(&flags.to_le_bytes());
+            b.extend_from_slice(&[0; 8]);
+            b
+        };
+        let mut tag = if header { block(1 << 31 | 1 << 29) } else { Vec::new() };
+        tag.extend_from_slice(items);
+        tag.extend(block(1 << 31));
+        tag
+    }
+
+    #[test]
+    fn frame_lengths() {
+        assert_eq!(frame_len(&[0xFF, 0xFB, 0x90, 0x00]), Some(417));
+        assert_eq!(frame_len(&[0xFF, 0xFB, 0x92, 0x00]), Some(418));
+        assert_eq!(frame_len(&[0xFF, 0xF3, 0x80, 0x00]), Some(208));
+        assert_eq!(frame_len(&[0xFF, 0xFD, 0x90, 0x00]), Some(522));
+        assert_eq!(frame_len(&[0xFF, 0xFF, 0x90, 0x00]), Some(312));
+        assert_eq!(frame_len(&[0xFF, 0xFB, 0x00, 0x00]), None);
+        assert_eq!(frame_len(&[0xFF, 0xEB, 0x90, 0x00]), None);
+    }
+
+    #[test]
+    fn strips_tags_and_reads_id3v2() {
+        let mut stream = id3v2(&[(b"TIT2", b"Song"), (b"TPE1", b"Band"), (b"TALB", b"Album"), (b"APIC", &[0xFF; 700])]);
+        stream.extend(frame(1));
+        stream.extend(frame(2));
+        stream.extend(ape(true));
+        stream.extend(frame(3));
+        stream.extend(ape(false));
+        let mut v1 = b"TAG".to_vec();
+        v1.resize(128, b' ');
+        stream.extend(v1);
+        stream.extend(frame(4));
+
+        let mut expected = frame(1);
+        expected.extend(frame(2));
+        expected.extend(frame(3));
+        expected.extend(frame(4));
+
+        let mut filter = ShoutMp3Filter::new();
+        let mut out = Vec::new();
+        let found: Vec<_> = stream.chunks(7).filter_map(|c| filter.filter(c, &mut out)).collect();
+        filter.finish(&mut out);
+        assert_eq!(out, expected);
+        assert_eq!(found, vec![ShoutMetadata::from_pairs(vec![
+            ("song", "Band - Song"), ("title", "Song"), ("artist", "Band"), ("album", "Album"),
+        ]).unwrap()]);
+    }
+
+    #[test]
+    fn passes_unknown_data_through() {
+        let mut stream = b"not mpeg".to_vec();
+        stream.extend(frame(1));
+        stream.extend_from_slice(b"\xFF\xFB");
+        let mut filter = ShoutMp3Filter::new();
+        let mut out = Vec::new();
+        assert_eq!(filter.filter(&stream, &mut out), None);
+        assert_eq!(out.len(), stream.len() - 2);
+        filter.finish(&mut out);
+        assert_eq!(out, stream);
+    }
+
+    #[test]
+    fn send_skips_empty_output() {
+        let tag = id3v2(&[(b"TIT2", b"Song")]);
+        let server = ::test_support::StandIn::start();
+        let conn = server.builder().configure().unwrap();
+        let found = ShoutMp3Filter::new().send(&conn, &tag).unwrap().unwrap();
+        assert!(found.applied.is_err());
+
+        conn.open().unwrap();
+        let mut filter = ShoutMp3Filter::new();
+        let found = filter.send(&conn, &tag).unwrap().unwrap();
+        assert_eq!(found.metadata.get("song"), Some("Song"));
+        assert_eq!(found.applied, Ok(()));
+        let mut frames = frame(1);
+        frames.extend(frame(2));
+        assert_eq!(filter.send(&conn, &frames).unwrap(), None);
+        conn.close().unwrap();
+        assert_eq!(server.songs(), vec!["Song"]);
+        assert_eq!(server.finish(), frames);
+    }
+
+    #[test]
+    fn decodes_text_encodings() {
+        assert_eq!(decode_text(b"\x00caf\xe9"), Some(String::from("caf\u{e9}")));
+        assert_eq!(decode_text(b"\x01\xFF\xFEh\0i\0\0\0x\0"), Some(String::from("hi")));
+        assert_eq!(decode_text(b"\x01\xFE\xFF\0h\0i"), Some(String::from("hi")));
+        assert_eq!(decode_text(b"\x02\0h\0i"), Some(String::from("hi")));
+        assert_eq!(decode_text(b"\x03 \xc3\xa9 "), Some(String::from("\u{e9}")));
+        assert_eq!(decode_text(b"\x03"), None);
+    }
+}
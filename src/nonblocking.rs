@@ -0,0 +1,116 @@
+//! Nonblocking connections, so that one thread can drive many mounts.
+
+use sys;
+
+use {ShoutConn, ShoutConnError, ShoutConnState, ShoutErr, ShoutError};
+
+/// Type representing the outcome of `ShoutNonblockingConn::try_send`
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ShoutTrySend {
+    /// The data was taken by libshout, which queues whatever the socket doesn't accept. This
+    /// many bytes are still waiting on the write queue; the data must not be sent again.
+    Queued(usize),
+    /// Only returned for an empty send, which just flushes the write queue: the socket is busy
+    /// and data is still queued.
+    WouldBlock,
+}
+
+/// A connection in libshout's nonblocking mode. Neither opening nor sending waits on the
+/// network: `poll_open` advances the connection a step at a time, and `try_send` queues what the
+/// socket doesn't accept instead of waiting for it. Callers apply backpressure by checking
+/// `queue_len` before handing over more data.
+pub struct ShoutNonblockingConn {
+    conn: ShoutConn,
+}
+
+impl ShoutNonblockingConn {
+    /// Switches an unconnected handle, such as one from `ShoutConnBuilder::configure`, to
+    /// nonblocking mode. Fails if the handle is still to detect its format, which needs the
+    /// first chunk before connecting.
+    pub fn new(conn: ShoutConn) -> Result<ShoutNonblockingConn, ShoutConnError> {
+        if conn.detect_format.get() {
+            let message = String::from("format detection needs a blocking connection");
+            return Err(ShoutError::with_message("set_nonblocking", ShoutErr::Insane, message).into());
+        }
+        conn.set_nonblocking(1)?;
+        Ok(ShoutNonblockingConn { conn })
+    }
+
+    /// Starts connecting if no attempt is in progress, otherwise advances the pending attempt.
+    /// Returns `Connecting` until the connection is established, and fails with the error which
+    /// ended the attempt. Polling again after a failure starts a new attempt.
+    pub fn poll_open(&self) -> Result<ShoutConnState, ShoutError> {
+        match self.conn.state()? {
+            ShoutConnState::Unconnected => {}
+            state => return Ok(state),
+        }
+        let shout = self.conn.shout.as_ptr();
+        let res = unsafe { sys::shout_open(shout) };
+        match ShoutErr::from_raw(res) {
+            ShoutErr::Success => Ok(ShoutConnState::Connected),
+            ShoutErr::Busy => Ok(ShoutConnState::Connecting),
+            _ => Err(unsafe { ShoutError::from_shout(shout, "open", res) }),
+        }
+    }
+
+    /// Hands data to libshout without waiting for the socket. On success, returns how many bytes
+    /// are left on the write queue.
+    pub fn try_send(&self, data: &[u8]) -> Result<ShoutTrySend, ShoutError> {
+        match self.conn.send(data) {
+            Ok(()) => Ok(ShoutTrySend::Queued(self.queue_len())),
+            Err(ref e) if e.code() == ShoutErr::Busy || e.code() == ShoutErr::Retry => {
+                // libshout queued the data before finding the socket busy.
+                if data.is_empty() {
+                    Ok(ShoutTrySend::WouldBlock)
+                } else {
+                    Ok(ShoutTrySend::Queued(self.queue_len()))
+                }
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns the number of bytes waiting on the write queue.
+    pub fn queue_len(&self) -> usize {
+        self.conn.queue_len().max(0) as usize
+    }
+
+    pub fn conn(&self) -> &ShoutConn {
+        &self.conn
+    }
+
+    pub fn into_inner(self) -> ShoutConn {
+        self.conn
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use ShoutConnBuilder;
+
+    #[test]
+    fn poll_open_reports_failure() {
+        let _serial = ::test_support::serial();
+        let conn = ShoutConnBuilder::new()
+            .host(String::from("127.0.0.1"))
+            .port(::test_support::closed_port())
+            .password(String::from("pw"))
+            .configure()
+            .unwrap();
+        let conn = ShoutNonblockingConn::new(conn).unwrap();
+        assert_eq!(conn.conn().nonblocking(), 1);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let err = loop {
+            match conn.poll_open() {
+                Err(e) => break e,
+                Ok(_) => assert!(Instant::now() < deadline, "connecting to a closed port should fail"),
+            }
+            thread::sleep(Duration::from_millis(1));
+        };
+        assert_eq!(err.code(), ShoutErr::NoConnect);
+        assert_eq!(conn.try_send(b"data").unwrap_err().code(), ShoutErr::Unconnected);
+    }
+}
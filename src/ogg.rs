@@ -0,0 +1,291 @@
+//! Now-playing metadata from the comment headers of Ogg Vorbis and Opus streams.
+
+use {read_le, ShoutConn, ShoutError, ShoutFoundMetadata, ShoutMetadata};
+
+/// Comment packets are only buffered up to this size; the comments before the limit, normally
+/// everything but embedded cover art, are still read.
+const MAX_COMMENT_LEN: usize = 64 * 1024;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Codec {
+    Vorbis,
+    Opus,
+}
+
+impl Codec {
+    /// Identifies a codec from the first packet of a logical stream.
+    fn from_header(packet: &[u8]) -> Option<Codec> {
+        if packet.starts_with(b"\x01vorbis") {
+            Some(Codec::Vorbis)
+        } else if packet.starts_with(b"OpusHead") {
+            Some(Codec::Opus)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the prefix of the codec's comment header packet.
+    fn comment_magic(self) -> &'static [u8] {
+        match self {
+            Codec::Vorbis => b"\x03vorbis",
+            Codec::Opus => b"OpusTags",
+        }
+    }
+}
+
+/// A logical stream whose comment header hasn't been read yet
+struct LogicalStream {
+    serial: u32,
+    codec: Option<Codec>,
+    packets: usize,
+    packet: Vec<u8>,
+}
+
+/// Reads a little-endian `u32` at `pos`, if the data is long enough.
+fn read_u32(data: &[u8], pos: usize) -> Option<u32> {
+    data.get(pos..pos.checked_add(4)?).map(|bytes| read_le(bytes) as u32)
+}
+
+/// Parses the comments of a Vorbis comment header, without its codec specific prefix. Parsing
+/// stops at the first comment which is cut off.
+fn parse_comments(data: &[u8]) -> Vec<(String, String)> {
+    let mut comments = Vec::new();
+    let vendor_len = match read_u32(data, 0) {
+        Some(len) => len as usize,
+        None => return comments,
+    };
+    let mut pos = 4usize.saturating_add(vendor_len);
+    let count = match read_u32(data, pos) {
+        Some(count) => count,
+        None => return comments,
+    };
+    pos += 4;
+    for _ in 0..count {
+        let len = match read_u32(data, pos) {
+            Some(len) => len as usize,
+            None => break,
+        };
+        let comment = match data.get(pos + 4..(pos + 4).saturating_add(len)) {
+            Some(comment) => String::from_utf8_lossy(comment),
+            None => break,
+        };
+        pos += 4 + len;
+        if let Some(eq) = comment.find('=') {
+            comments.push((comment[..eq].to_ascii_uppercase(), comment[eq + 1..].to_owned()));
+        }
+    }
+    comments
+}
+
+/// Builds now-playing metadata from the TITLE, ARTIST and ALBUM comments, if there are any.
+fn comments_to_metadata(comments: &[(String, String)]) -> Option<ShoutMetadata> {
+    // Values with NUL bytes can't be passed to libshout.
+    let find = |key: &str| comments.iter().find(|c| c.0 == key && !c.1.contains('\0')).map(|c| c.1.clone());
+    ShoutMetadata::from_tags(find("TITLE"), find("ARTIST"), find("ALBUM"))
+}
+
+/// Watches an Ogg stream for new logical streams and reads the now-playing metadata from their
+/// Vorbis or OpusTags comment headers. Data may be fed in chunks of any size.
+pub struct ShoutOggInspector {
+    buf: Vec<u8>,
+    streams: Vec<LogicalStream>,
+}
+
+impl Default for ShoutOggInspector {
+    fn default() -> ShoutOggInspector {
+        ShoutOggInspector::new()
+    }
+}
+
+impl ShoutOggInspector {
+    pub fn new() -> ShoutOggInspector {
+        ShoutOggInspector {
+            buf: Vec::new(),
+            streams: Vec::new(),
+        }
+    }
+
+    /// Inspects the next chunk of the stream, returning the metadata of the last comment header
+    /// completed by it.
+    pub fn inspect(&mut self, data: &[u8]) -> Option<ShoutMetadata> {
+        self.buf.extend_from_slice(data);
+        let mut metadata = None;
+        let mut pos = 0;
+        loop {
+            // Skip to the next capture pattern, keeping a possible partial one.
+            match self.buf[pos..].windows(4).position(|w| w == b"OggS") {
+                Some(offset) => pos += offset,
+                None => {
+                    pos = self.buf.len().saturating_sub(3).max(pos);
+                    break;
+                }
+            }
+            let page = &self.buf[pos..];
+            if page.len() < 27 {
+                break;
+            }
+            let segments = page[26] as usize;
+            if page.len() < 27 + segments {
+                break;
+            }
+            let lacing = &page[27..27 + segments];
+            let body_len = lacing.iter().map(|&l| l as usize).sum::<usize>();
+            let header_len = 27 + segments;
+            if page.len() < header_len + body_len {
+                break;
+            }
+            let flags = page[5];
+            let serial = read_u32(page, 14).unwrap();
+            let page_metadata = Self::read_page(&mut self.streams, flags, serial, lacing, &page[header_len..header_len + body_len]);
+            metadata = page_metadata.or(metadata);
+            pos += header_len + body_len;
+        }
+        self.buf.drain(..pos);
+        metadata
+    }
+
+    /// Inspects the chunk and sends it, then sets any metadata found on the server.
+    pub fn send(&mut self, conn: &ShoutConn, data: &[u8]) -> Result<Option<ShoutFoundMetadata>, ShoutError> {
+        let metadata = self.inspect(data);
+        conn.send_found(data, metadata)
+    }
+
+    fn read_page(streams: &mut Vec<LogicalStream>, flags: u8, serial: u32, lacing: &[u8], body: &[u8]) -> Option<ShoutMetadata> {
+        const BOS: u8 = 0x02;
+        const EOS: u8 = 0x04;
+
+        if flags & BOS != 0 {
+            streams.retain(|s| s.serial != serial);
+            streams.push(LogicalStream {
+                serial,
+                codec: None,
+                packets: 0,
+                packet: Vec::new(),
+            });
+        }
+        let index = streams.iter().position(|s| s.serial == serial)?;
+        let mut metadata = None;
+        let mut done = false;
+        {
+            let stream = &mut streams[index];
+            let mut offset = 0;
+            for &len in lacing {
+                let segment = &body[offset..offset + len as usize];
+                offset += len as usize;
+                let room = MAX_COMMENT_LEN.saturating_sub(stream.packet.len());
+                stream.packet.extend_from_slice(&segment[..segment.len().min(room)]);
+                if len == 255 {
+                    continue;
+                }
+                if stream.packets == 0 {
+                    stream.codec = Codec::from_header(&stream.packet);
+                } else if let Some(codec) = stream.codec {
+                    let magic = codec.comment_magic();
+                    if stream.packet.starts_with(magic) {
+                        metadata = comments_to_metadata(&parse_comments(&stream.packet[magic.len()..]));
+                    }
+                }
+                stream.packets += 1;
+                stream.packet.clear();
+                if stream.codec.is_none() || stream.packets > 1 {
+                    done = true;
+                    break;
+                }
+            }
+        }
+        if done || flags & EOS != 0 {
+            streams.remove(index);
+        }
+        metadata
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ShoutFormat;
+
+    fn page(serial: u32, flags: u8, packets: &[&[u8]]) -> Vec<u8> {
+        let mut lacing = Vec::new();
+        let mut body = Vec::new();
+        for packet in packets {
+            lacing.extend(vec![255; packet.len() / 255]);
+            lacing.push((packet.len() % 255) as u8);
+            body.extend_from_slice(packet);
+        }
+        let mut page = b"OggS\0".to_vec();
+        page.push(flags);
+        page.extend_from_slice(&[0; 8]);
+        page.extend_from_slice(&serial.to_le_bytes());
+        page.extend_from_slice(&[0; 8]);
+        page.push(lacing.len() as u8);
+        page.extend(lacing);
+        page.extend(body);
+        page
+    }
+
+    fn comment_header(magic: &[u8], comments: &[&str]) -> Vec<u8> {
+        let mut packet = magic.to_vec();
+        packet.extend_from_slice(&6u32.to_le_bytes());
+        packet.extend_from_slice(b"vendor");
+        packet.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+        for comment in comments {
+            packet.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+            packet.extend_from_slice(comment.as_bytes());
+        }
+        packet
+    }
+
+    #[test]
+    fn reads_vorbis_comments_across_chunks() {
+        let padding = format!("DESCRIPTION={}", "x".repeat(600));
+        let mut stream = page(1, 0x02, &[b"\x01vorbis header"]);
+        stream.extend(page(1, 0, &[&comment_header(b"\x03vorbis", &["title=Song", &padding, "ARTIST=Band"]), b"\x05vorbis setup"]));
+        stream.extend(page(1, 0x04, &[b"audio"]));
+
+        let mut inspector = ShoutOggInspector::new();
+        let found: Vec<_> = stream.chunks(100).filter_map(|c| inspector.inspect(c)).collect();
+        assert_eq!(found, vec![ShoutMetadata::from_pairs(vec![
+            ("song", "Band - Song"), ("title", "Song"), ("artist", "Band"),
+        ]).unwrap()]);
+        assert!(inspector.streams.is_empty());
+    }
+
+    #[test]
+    fn reads_opus_tags_of_chained_streams() {
+        let mut inspector = ShoutOggInspector::new();
+        assert_eq!(inspector.inspect(&page(7, 0x02, &[b"\x80theora"])), None);
+        assert_eq!(inspector.inspect(&page(7, 0, &[b"\x81theora TITLE=Ignored"])), None);
+        for title in &["One", "Two"] {
+            let mut stream = b"garbage".to_vec();
+            stream.extend(page(9, 0x02, &[b"OpusHead"]));
+            stream.extend(page(9, 0, &[&comment_header(b"OpusTags", &[&format!("TITLE={}", title)])]));
+            let metadata = inspector.inspect(&stream).unwrap();
+            assert_eq!(metadata.get("song"), Some(*title));
+            assert_eq!(metadata.get("artist"), None);
+        }
+    }
+
+    #[test]
+    fn send_reports_metadata_separately() {
+        let mut stream = page(1, 0x02, &[b"OpusHead"]);
+        stream.extend(page(1, 0, &[&comment_header(b"OpusTags", &["ARTIST=Band"])]));
+
+        let server = ::test_support::StandIn::start();
+        let conn = server.builder().build().unwrap();
+        let found = ShoutOggInspector::new().send(&conn, &stream).unwrap().unwrap();
+        assert_eq!(found.metadata.get("song"), Some("Band"));
+        assert_eq!(found.applied, Ok(()));
+        conn.close().unwrap();
+        assert_eq!(server.songs(), vec!["Band"]);
+        assert_eq!(server.finish(), stream);
+
+        let server = ::test_support::StandIn::start();
+        let conn = server.builder().mount(String::from("/test.ogg")).format(ShoutFormat::Ogg).build().unwrap();
+        let found = ShoutOggInspector::new().send(&conn, &stream).unwrap().unwrap();
+        assert!(found.applied.is_err());
+        conn.close().unwrap();
+        assert!(server.songs().is_empty());
+        assert_eq!(server.finish(), stream);
+    }
+}
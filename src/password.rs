@@ -0,0 +1,70 @@
+//! A password type which keeps the secret out of logs and memory dumps.
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::ptr;
+use std::sync::atomic::{self, Ordering};
+
+/// Overwrites `bytes` with zeroes in a way the compiler won't optimize away.
+pub(crate) fn zero(bytes: &mut [u8]) {
+    for b in bytes.iter_mut() {
+        unsafe { ptr::write_volatile(b, 0) };
+    }
+    atomic::compiler_fence(Ordering::SeqCst);
+}
+
+/// A password which is redacted from `Debug` output and zeroed in memory when dropped.
+#[derive(Clone, Eq, PartialEq)]
+pub struct ShoutPassword(String);
+
+impl ShoutPassword {
+    pub fn new(password: String) -> ShoutPassword {
+        ShoutPassword(password)
+    }
+
+    /// Reads the password from an environment variable.
+    pub fn from_env(var: &str) -> Result<ShoutPassword, env::VarError> {
+        env::var(var).map(ShoutPassword)
+    }
+
+    /// Reads the password from a file, ignoring a trailing line break.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<ShoutPassword> {
+        let mut password = ShoutPassword(fs::read_to_string(path)?);
+        let len = password.0.trim_end_matches(['\n', '\r']).len();
+        unsafe { zero(&mut password.0.as_mut_vec()[len..]) };
+        password.0.truncate(len);
+        Ok(password)
+    }
+
+    /// Returns the password itself.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for ShoutPassword {
+    fn from(password: String) -> Self {
+        ShoutPassword(password)
+    }
+}
+
+impl<'a> From<&'a str> for ShoutPassword {
+    fn from(password: &'a str) -> Self {
+        ShoutPassword(password.to_owned())
+    }
+}
+
+impl fmt::Debug for ShoutPassword {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ShoutPassword(***)")
+    }
+}
+
+impl Drop for ShoutPassword {
+    fn drop(&mut self) {
+        unsafe { zero(self.0.as_mut_vec()) }
+    }
+}
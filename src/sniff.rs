@@ -0,0 +1,255 @@
+//! Detecting the format of a stream from its first bytes.
+
+use std::ptr;
+
+use mp3::frame_len;
+use {ShoutConn, ShoutError, ShoutFormat, ShoutUsage, ShoutUsages};
+
+/// Reads an EBML variable length integer at `pos`, returning it with its length. Element IDs
+/// keep their length marker; sizes don't, and an all-ones size, meaning unknown, is `None`.
+fn read_vint(data: &[u8], pos: usize, keep_marker: bool) -> Option<(Option<u64>, usize)> {
+    let first = *data.get(pos)?;
+    let len = first.leading_zeros() as usize + 1;
+    if len > 8 {
+        return None;
+    }
+    let bytes = data.get(pos..pos + len)?;
+    let marker = if keep_marker { first } else { first & (0xFFu16 >> len) as u8 };
+    let value = bytes[1..].iter().fold(u64::from(marker), |v, &b| v << 8 | u64::from(b));
+    let unknown = !keep_marker && value == (1 << (7 * len)) - 1;
+    Some((if unknown { None } else { Some(value) }, len))
+}
+
+/// Levels of EBML elements read. TrackType, the deepest element needed, sits inside Segment,
+/// Tracks and TrackEntry.
+const MAX_EBML_DEPTH: usize = 4;
+
+/// Walks the EBML elements in `data`, at `depth` counted from 1, descending into the header,
+/// segment and track list, and records the document type and the usages of the tracks.
+fn walk_ebml(data: &[u8], depth: usize, doctype: &mut Option<String>, usage: &mut ShoutUsages) {
+    let mut pos = 0;
+    while let Some((Some(id), id_len)) = read_vint(data, pos, true) {
+        let (size, size_len) = match read_vint(data, pos + id_len, false) {
+            Some(size) => size,
+            None => return,
+        };
+        let start = pos + id_len + size_len;
+        let end = size.map_or(data.len(), |s| (start as u64).saturating_add(s).min(data.len() as u64) as usize);
+        let body = &data[start.min(end)..end];
+        match id {
+            // EBML header, Segment, Tracks, TrackEntry
+            0x1A45_DFA3 | 0x1853_8067 | 0x1654_AE6B | 0xAE if depth < MAX_EBML_DEPTH => {
+                walk_ebml(body, depth + 1, doctype, usage)
+            }
+            // DocType
+            0x4282 => *doctype = Some(String::from_utf8_lossy(body).trim_end_matches('\0').to_owned()),
+            // TrackType
+            0x83 => match body.first() {
+                Some(1) => usage.insert(ShoutUsage::Visual),
+                Some(2) => usage.insert(ShoutUsage::Audio),
+                Some(0x11) => usage.insert(ShoutUsage::Subtitle),
+                Some(0x20) => usage.insert(ShoutUsage::Metadata),
+                _ => {}
+            },
+            _ => {}
+        }
+        if size.is_none() {
+            return;
+        }
+        pos = end;
+    }
+}
+
+/// Returns the usages of the logical streams starting at the beginning of an Ogg stream.
+fn ogg_usage(data: &[u8]) -> ShoutUsages {
+    let mut usage = ShoutUsages::empty();
+    let mut pos = 0;
+    // Every logical stream starts with a page flagged BOS, before any other page.
+    while data.get(pos..pos + 4) == Some(b"OggS") && data.get(pos + 5).is_some_and(|f| f & 0x02 != 0) {
+        let segments = match data.get(pos + 26) {
+            Some(&n) => n as usize,
+            None => break,
+        };
+        let lacing = match data.get(pos + 27..pos + 27 + segments) {
+            Some(lacing) => lacing,
+            None => break,
+        };
+        let body = &data[(pos + 27 + segments).min(data.len())..];
+        if body.starts_with(b"\x01vorbis") || body.starts_with(b"OpusHead") || body.starts_with(b"\x7fFLAC")
+            || body.starts_with(b"Speex   ") {
+            usage.insert(ShoutUsage::Audio);
+        } else if body.starts_with(b"\x80theora") {
+            usage.insert(ShoutUsage::Visual);
+        } else if body.starts_with(b"\x80kate\0\0\0") {
+            usage.insert(ShoutUsage::Subtitle);
+        }
+        pos += 27 + segments + lacing.iter().map(|&l| l as usize).sum::<usize>();
+    }
+    usage
+}
+
+impl ShoutFormat {
+    /// Detects the format of a stream from its first bytes: the Ogg capture pattern, an ID3v2
+    /// tag or MP3 frame sync, or an EBML header with a `webm` or `matroska` document type.
+    /// The usage is read from the Ogg stream headers or Matroska track list when the bytes
+    /// include them. Returns `None` if the format isn't recognised.
+    pub fn detect(data: &[u8]) -> Option<(ShoutFormat, ShoutUsages)> {
+        if data.starts_with(b"OggS") {
+            let usage = ogg_usage(data);
+            let usage = if usage.is_empty() { ShoutUsage::Audio.into() } else { usage };
+            return Some((ShoutFormat::Ogg, usage));
+        }
+        if data.starts_with(b"\x1A\x45\xDF\xA3") {
+            let (mut doctype, mut usage) = (None, ShoutUsages::empty());
+            walk_ebml(data, 1, &mut doctype, &mut usage);
+            if usage.is_empty() {
+                usage = ShoutUsage::Audio | ShoutUsage::Visual;
+            }
+            return match doctype.as_deref() {
+                Some("webm") => Some((ShoutFormat::Webm, usage)),
+                Some("matroska") => Some((ShoutFormat::Matroska, usage)),
+                _ => None,
+            };
+        }
+        if data.starts_with(b"ID3") {
+            return Some((ShoutFormat::MP3, ShoutUsage::Audio.into()));
+        }
+        // Require the next frame to follow when there's data for it, as a frame sync alone is
+        // easily found by chance.
+        let len = frame_len(data.get(..4)?)?;
+        match data.get(len..len + 4) {
+            Some(next) if frame_len(next).is_none() => None,
+            _ => Some((ShoutFormat::MP3, ShoutUsage::Audio.into())),
+        }
+    }
+}
+
+impl ShoutConn {
+    /// Sets the format detected from the first chunk, if it is recognised, and connects.
+    pub(crate) fn open_detected(&self, data: &[u8]) -> Result<(), ShoutError> {
+        if let Some((format, usage)) = ShoutFormat::detect(data) {
+            let shout = self.shout.as_ptr();
+            let res = unsafe { sys::shout_set_content_format(shout, format.to_raw(), usage.bits(), ptr::null()) };
+            if res != 0 {
+                return Err(unsafe { ShoutError::from_shout(shout, "set_content_format", res) });
+            }
+        }
+        self.connect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {ShoutConnError, ShoutErr};
+
+    fn ogg_bos(packet: &[u8]) -> Vec<u8> {
+        let mut page = b"OggS\0\x02".to_vec();
+        page.extend_from_slice(&[0; 20]);
+        page.push(1);
+        page.push(packet.len() as u8);
+        page.extend_from_slice(packet);
+        page
+    }
+
+    fn ebml(id: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut element = id.to_vec();
+        element.push(0x80 | body.len() as u8);
+        element.extend_from_slice(body);
+        element
+    }
+
+    fn mp3_frames(n: usize) -> Vec<u8> {
+        let mut frame = vec![0xFF, 0xFB, 0x90, 0x00];
+        frame.resize(417, 0);
+        frame.repeat(n)
+    }
+
+    #[test]
+    fn detects_ogg_usage() {
+        let mut data = ogg_bos(b"\x80theora");
+        data.extend(ogg_bos(b"OpusHead"));
+        assert_eq!(ShoutFormat::detect(&data), Some((ShoutFormat::Ogg, ShoutUsage::Audio | ShoutUsage::Visual)));
+        assert_eq!(ShoutFormat::detect(b"OggS"), Some((ShoutFormat::Ogg, ShoutUsage::Audio.into())));
+    }
+
+    #[test]
+    fn detects_mp3() {
+        let audio = Some((ShoutFormat::MP3, ShoutUsage::Audio.into()));
+        assert_eq!(ShoutFormat::detect(&mp3_frames(2)), audio);
+        assert_eq!(ShoutFormat::detect(&mp3_frames(1)[..100]), audio);
+        assert_eq!(ShoutFormat::detect(b"ID3\x04\0\0\0\0\0\0"), audio);
+        let mut noise = mp3_frames(1);
+        noise.extend_from_slice(b"noise");
+        assert_eq!(ShoutFormat::detect(&noise), None);
+        assert_eq!(ShoutFormat::detect(b"\xFF\xF1\x50\x80"), None);
+    }
+
+    #[test]
+    fn detects_webm_and_matroska() {
+        let header = |doctype: &[u8]| ebml(b"\x1A\x45\xDF\xA3", &ebml(b"\x42\x82", doctype));
+        let track = |kind: u8| ebml(b"\xAE", &ebml(b"\x83", &[kind]));
+
+        let mut webm = header(b"webm");
+        let mut segment = ebml(b"\x15\x49\xA9\x66", b"info");
+        segment.extend(ebml(b"\x16\x54\xAE\x6B", &track(2)));
+        webm.extend_from_slice(b"\x18\x53\x80\x67\x01\xFF\xFF\xFF\xFF\xFF\xFF\xFF");
+        webm.extend(segment);
+        assert_eq!(ShoutFormat::detect(&webm), Some((ShoutFormat::Webm, ShoutUsage::Audio.into())));
+
+        let mkv = header(b"matroska");
+        assert_eq!(ShoutFormat::detect(&mkv), Some((ShoutFormat::Matroska, ShoutUsage::Audio | ShoutUsage::Visual)));
+        assert_eq!(ShoutFormat::detect(&header(b"other")), None);
+        assert_eq!(ShoutFormat::detect(b"RIFF"), None);
+    }
+
+    #[test]
+    fn limits_ebml_nesting() {
+        let mut nested = b"\x1A\x45\xDF\xA3\xFF".to_vec();
+        nested.extend(b"\xAE\xFF".repeat(16 * 1024));
+        assert_eq!(ShoutFormat::detect(&nested), None);
+    }
+
+    #[test]
+    fn builder_detects_on_first_send() {
+        let server = ::test_support::StandIn::start();
+        let conn = ::test_support::local_builder(::test_support::closed_port())
+            .format(ShoutFormat::Ogg)
+            .detect_format(true)
+            .build()
+            .unwrap();
+        assert!(!conn.is_connected());
+        assert_eq!(conn.send(&[]).unwrap_err().code(), ShoutErr::Unconnected);
+        assert_eq!(conn.flush_queue().unwrap_err().code(), ShoutErr::Unconnected);
+        assert_eq!(conn.send(&mp3_frames(2)).unwrap_err().code(), ShoutErr::NoConnect);
+        assert!(!conn.is_connected());
+
+        conn.set_port(server.port()).unwrap();
+        conn.send(&mp3_frames(2)).unwrap();
+        assert!(conn.is_connected());
+        assert_eq!(conn.format(), ShoutFormat::MP3);
+        conn.close().unwrap();
+        assert_eq!(server.finish(), mp3_frames(2));
+    }
+    #[test]
+    fn open_waits_for_first_chunk() {
+        let server = ::test_support::StandIn::start();
+        let conn = server.builder().format(ShoutFormat::Ogg).detect_format(true).configure().unwrap();
+        conn.open().unwrap();
+        assert!(!conn.is_connected());
+        conn.send(&mp3_frames(2)).unwrap();
+        assert_eq!(conn.format(), ShoutFormat::MP3);
+        conn.close().unwrap();
+        assert_eq!(server.finish(), mp3_frames(2));
+    }
+
+    #[test]
+    fn nonblocking_rejects_detection() {
+        let _serial = ::test_support::serial();
+        let builder = ::test_support::local_builder(::test_support::closed_port()).detect_format(true);
+        match builder.build_nonblocking() {
+            Err(ShoutConnError::ShoutError(e)) => assert_eq!(e.code(), ShoutErr::Insane),
+            _ => panic!("expected format detection to be refused"),
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Splitting a connection between a streaming thread and any number of control threads.
+
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use {ShoutCancel, ShoutConn, ShoutConnError, ShoutConnState, ShoutError, ShoutMetadata, ShoutSyncStatus};
+
+fn lock(conn: &Mutex<ShoutConn>) -> MutexGuard<'_, ShoutConn> {
+    conn.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl ShoutConn {
+    /// Splits the connection into a sender, to be owned by the thread streaming audio, and a
+    /// cloneable control handle for updating metadata from other threads. Every call through
+    /// either half locks the libshout handle, so calls never overlap.
+    pub fn split(self) -> (ShoutSender, ShoutControl) {
+        let conn = Arc::new(Mutex::new(self));
+        (ShoutSender { conn: conn.clone() }, ShoutControl { conn })
+    }
+}
+
+/// The sending half of a split `ShoutConn`
+pub struct ShoutSender {
+    conn: Arc<Mutex<ShoutConn>>,
+}
+
+impl ShoutSender {
+    /// Sends data to the server, parsing it for format specific timing info.
+    pub fn send(&mut self, data: &[u8]) -> Result<(), ShoutError> {
+        lock(&self.conn).send(data)
+    }
+
+    /// Sleeps the thread until the server requires more data. Unlike `ShoutConn::sync`, the
+    /// handle isn't locked while sleeping, so control threads aren't held up.
+    pub fn sync(&self) {
+        let delay = lock(&self.conn).delay();
+        thread::sleep(delay);
+    }
+
+    /// Like `sync`, but waits for at most `timeout` and returns as soon as `cancel` is raised.
+    pub fn sync_timeout(&self, timeout: Duration, cancel: &ShoutCancel) -> ShoutSyncStatus {
+        let delay = lock(&self.conn).delay();
+        cancel.wait(delay, timeout)
+    }
+
+    /// Returns the number of bytes on the write queue. Only makes sense in nonblocking mode.
+    pub fn queue_len(&self) -> isize {
+        lock(&self.conn).queue_len()
+    }
+
+    /// Runs `f` with the handle locked.
+    pub fn with<F, T>(&self, f: F) -> T
+        where F: FnOnce(&ShoutConn) -> T
+    {
+        f(&lock(&self.conn))
+    }
+
+    /// Returns the connection, or the sender back if a `ShoutControl` is still alive.
+    pub fn into_inner(self) -> Result<ShoutConn, ShoutSender> {
+        match Arc::try_unwrap(self.conn) {
+            Ok(conn) => Ok(conn.into_inner().unwrap_or_else(|e| e.into_inner())),
+            Err(conn) => Err(ShoutSender { conn }),
+        }
+    }
+}
+
+/// The control half of a split `ShoutConn`, which may be cloned and shared between threads
+#[derive(Clone)]
+pub struct ShoutControl {
+    conn: Arc<Mutex<ShoutConn>>,
+}
+
+impl ShoutControl {
+    /// Sets metadata for the host
+    pub fn set_metadata(&self, metadata: &ShoutMetadata) -> Result<(), ShoutConnError> {
+        lock(&self.conn).set_metadata(metadata)
+    }
+
+    /// Returns the state of the connection.
+    pub fn state(&self) -> Result<ShoutConnState, ShoutError> {
+        lock(&self.conn).state()
+    }
+
+    /// Returns whether the handle is currently connected to the host.
+    pub fn is_connected(&self) -> bool {
+        lock(&self.conn).is_connected()
+    }
+
+    /// Runs `f` with the handle locked.
+    pub fn with<F, T>(&self, f: F) -> T
+        where F: FnOnce(&ShoutConn) -> T
+    {
+        f(&lock(&self.conn))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata_updates_while_streaming() {
+        let server = ::test_support::StandIn::start();
+        let (mut sender, control) = server.builder().build().unwrap().split();
+
+        let updater = {
+            let control = control.clone();
+            thread::spawn(move || {
+                for i in 0..10 {
+                    let mut metadata = ShoutMetadata::new();
+                    metadata.set_song(format!("Song {}", i)).unwrap();
+                    control.set_metadata(&metadata).unwrap();
+                }
+            })
+        };
+        for _ in 0..10 {
+            sender.send(b"data").unwrap();
+            sender.sync();
+        }
+        updater.join().unwrap();
+
+        assert!(control.is_connected());
+        let songs: Vec<_> = (0..10).map(|i| format!("Song {}", i)).collect();
+        assert_eq!(server.songs(), songs);
+        let sender = match sender.into_inner() {
+            Ok(_) => panic!("the control half is still alive"),
+            Err(sender) => sender,
+        };
+        drop(control);
+        sender.into_inner().ok().unwrap().close().unwrap();
+        assert_eq!(server.finish().len(), 40);
+    }
+}
@@ -0,0 +1,218 @@
+//! Streaming a reader to the server, paced to the stream's bitrate.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use {ShoutConn, ShoutError};
+
+type ProgressCallback<'a> = Box<dyn FnMut(&ShoutStreamProgress) + 'a>;
+
+/// Type representing how `ShoutConn::stream_from` reads and sends data
+pub struct ShoutStreamOptions<'a> {
+    chunk_size: usize,
+    pace: bool,
+    on_progress: Option<ProgressCallback<'a>>,
+    cancel: Option<&'a AtomicBool>,
+}
+
+impl<'a> Default for ShoutStreamOptions<'a> {
+    fn default() -> ShoutStreamOptions<'a> {
+        ShoutStreamOptions {
+            chunk_size: 4096,
+            pace: true,
+            on_progress: None,
+            cancel: None,
+        }
+    }
+}
+
+impl<'a> ShoutStreamOptions<'a> {
+    pub fn new() -> ShoutStreamOptions<'a> {
+        ShoutStreamOptions::default()
+    }
+
+    /// Sets how many bytes are read and sent at a time. Defaults to 4096.
+    pub fn chunk_size(mut self, chunk_size: usize) -> ShoutStreamOptions<'a> {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Sets whether to wait for the server with `ShoutConn::sync` between chunks. Enabled by
+    /// default; disable it when the reader is already paced, such as a live encoder.
+    pub fn pace(mut self, pace: bool) -> ShoutStreamOptions<'a> {
+        self.pace = pace;
+        self
+    }
+
+    /// Sets a callback invoked after every chunk sent.
+    pub fn on_progress<F>(mut self, on_progress: F) -> ShoutStreamOptions<'a>
+        where F: FnMut(&ShoutStreamProgress) + 'a
+    {
+        self.on_progress = Some(Box::new(on_progress));
+        self
+    }
+
+    /// Sets a flag which stops streaming before the next chunk once it is set.
+    pub fn cancel(mut self, cancel: &'a AtomicBool) -> ShoutStreamOptions<'a> {
+        self.cancel = Some(cancel);
+        self
+    }
+}
+
+/// Type representing how far `ShoutConn::stream_from` has got
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ShoutStreamProgress {
+    pub bytes_sent: u64,
+    pub chunks_sent: u64,
+    pub elapsed: Duration,
+}
+
+/// Type representing what stopped `ShoutConn::stream_from` early
+#[derive(Debug)]
+pub enum ShoutStreamError {
+    /// Reading from the source failed
+    Read(io::Error),
+    /// Sending to the server failed
+    Send(ShoutError),
+}
+
+impl fmt::Display for ShoutStreamError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ShoutStreamError::Read(ref e) => write!(f, "reading the stream failed: {}", e),
+            ShoutStreamError::Send(ref e) => fmt::Display::fmt(e, f),
+        }
+    }
+}
+
+impl Error for ShoutStreamError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            ShoutStreamError::Read(ref e) => Some(e),
+            ShoutStreamError::Send(ref e) => Some(e),
+        }
+    }
+}
+
+/// Type representing the outcome of `ShoutConn::stream_from`
+#[derive(Debug)]
+#[must_use]
+pub struct ShoutStreamSummary {
+    pub bytes_sent: u64,
+    pub chunks_sent: u64,
+    pub elapsed: Duration,
+    /// Whether streaming was stopped by the cancel flag
+    pub cancelled: bool,
+    /// The error which stopped streaming, if any
+    pub error: Option<ShoutStreamError>,
+}
+
+/// Reads until `buf` is full or the reader is exhausted, returning how much was read.
+fn fill<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match reader.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(len)
+}
+
+impl ShoutConn {
+    /// Reads `reader` to the end and sends it to the server chunk by chunk. Streaming stops at
+    /// the end of the reader, when the cancel flag is set, or on the first error; the summary
+    /// says which.
+    pub fn stream_from<R: Read>(&self, mut reader: R, mut options: ShoutStreamOptions) -> ShoutStreamSummary {
+        let start = Instant::now();
+        let mut buf = vec![0; options.chunk_size];
+        let mut progress = ShoutStreamProgress {
+            bytes_sent: 0,
+            chunks_sent: 0,
+            elapsed: Duration::from_secs(0),
+        };
+        let mut cancelled = false;
+
+        let error = loop {
+            if options.cancel.is_some_and(|c| c.load(Ordering::SeqCst)) {
+                cancelled = true;
+                break None;
+            }
+            let len = match fill(&mut reader, &mut buf) {
+                Ok(0) => break None,
+                Ok(len) => len,
+                Err(e) => break Some(ShoutStreamError::Read(e)),
+            };
+            if options.pace && progress.chunks_sent > 0 {
+                self.sync();
+            }
+            if let Err(e) = self.send(&buf[..len]) {
+                break Some(ShoutStreamError::Send(e));
+            }
+            progress.bytes_sent += len as u64;
+            progress.chunks_sent += 1;
+            progress.elapsed = start.elapsed();
+            if let Some(ref mut on_progress) = options.on_progress {
+                on_progress(&progress);
+            }
+        };
+
+        ShoutStreamSummary {
+            bytes_sent: progress.bytes_sent,
+            chunks_sent: progress.chunks_sent,
+            elapsed: start.elapsed(),
+            cancelled,
+            error,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ShoutErr;
+
+    #[test]
+    fn streams_in_chunks() {
+        let server = ::test_support::StandIn::start();
+        let conn = server.builder().build().unwrap();
+        let mut sizes = Vec::new();
+        let summary = conn.stream_from(&b"0123456789"[..], ShoutStreamOptions::new()
+            .chunk_size(4)
+            .on_progress(|p| sizes.push(p.bytes_sent)));
+        assert_eq!((summary.bytes_sent, summary.chunks_sent), (10, 3));
+        assert!(!summary.cancelled);
+        assert!(summary.error.is_none());
+        assert_eq!(sizes, vec![4, 8, 10]);
+        drop(conn);
+        assert_eq!(server.finish(), b"0123456789");
+    }
+
+    #[test]
+    fn stops_on_cancel_and_error() {
+        let server = ::test_support::StandIn::start();
+        let conn = server.builder().configure().unwrap();
+        let summary = conn.stream_from(&b"data"[..], ShoutStreamOptions::new());
+        match summary.error {
+            Some(ShoutStreamError::Send(e)) => assert_eq!(e.code(), ShoutErr::Unconnected),
+            _ => panic!("expected sending to fail"),
+        }
+        assert_eq!(summary.bytes_sent, 0);
+
+        conn.open().unwrap();
+        let cancel = AtomicBool::new(false);
+        let summary = conn.stream_from(&b"0123456789"[..], ShoutStreamOptions::new()
+            .chunk_size(4)
+            .on_progress(|_| cancel.store(true, Ordering::SeqCst))
+            .cancel(&cancel));
+        assert_eq!(summary.bytes_sent, 4);
+        assert!(summary.cancelled);
+        drop(conn);
+        assert_eq!(server.finish(), b"0123");
+    }
+}
@@ -0,0 +1,330 @@
+//! Automatic reconnection of a `ShoutConn` when the server goes away.
+
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use {ShoutConn, ShoutConnError, ShoutErr, ShoutError, ShoutMetadata, ShoutMetadataThrottle};
+
+/// Type representing the delay policy between reconnection attempts. The n-th attempt of a
+/// `send` call waits `initial * multiplier^(n-1)`, capped at `max` and randomly spread by `jitter`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShoutBackoff {
+    /// Delay before the first attempt
+    pub initial: Duration,
+    /// Upper bound on the delay between attempts, before jitter
+    pub max: Duration,
+    /// Factor the delay grows by after every failed attempt
+    pub multiplier: f64,
+    /// Fraction of the delay, between 0 and 1, by which it's randomly lengthened or shortened
+    pub jitter: f64,
+    /// Number of attempts per `send` call before giving up, or `None` to retry forever. The
+    /// count is only reset once data is delivered, so a server which accepts the connection and
+    /// drops it again doesn't keep the supervisor retrying forever.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ShoutBackoff {
+    fn default() -> ShoutBackoff {
+        ShoutBackoff {
+            initial: Duration::from_millis(500),
+            max: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.2,
+            max_attempts: Some(10),
+        }
+    }
+}
+
+impl ShoutBackoff {
+    /// Returns the delay before the given attempt, counted from 1, for a jitter sample `r`
+    /// between -1 and 1.
+    fn delay(&self, attempt: u32, r: f64) -> Duration {
+        let exp = self.multiplier.powi(attempt.saturating_sub(1).min(i32::MAX as u32) as i32);
+        let base = (self.initial.as_secs_f64() * exp).min(self.max.as_secs_f64());
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        Duration::try_from_secs_f64((base * (1.0 + jitter * r)).max(0.0)).unwrap_or(self.max)
+    }
+}
+
+/// Type representing something that happened while the supervisor kept a connection alive
+#[derive(Debug)]
+pub enum ShoutSupervisorEvent<'a> {
+    /// Sending failed because the connection was lost
+    Disconnected(&'a ShoutError),
+    /// A reconnection attempt is about to be made after waiting `delay`
+    Attempt { attempt: u32, delay: Duration },
+    /// A reconnection attempt failed
+    AttemptFailed { attempt: u32, error: &'a ShoutConnError },
+    /// The connection was re-established
+    Reconnected { attempt: u32 },
+    /// The last metadata couldn't be re-applied after reconnecting
+    MetadataFailed(&'a ShoutConnError),
+    /// The attempt budget was used up without reconnecting
+    GaveUp { attempts: u32 },
+}
+
+type EventCallback = Box<dyn FnMut(&ShoutSupervisorEvent) + Send>;
+
+/// A wrapper around `ShoutConn` which reconnects with exponential backoff when sending fails
+/// with `ShoutErr::Socket` or `ShoutErr::Unconnected`. After reconnecting, the last metadata
+/// set through the supervisor is re-applied and the buffer which failed is sent again.
+pub struct ShoutSupervisor {
+    conn: ShoutConn,
+    backoff: ShoutBackoff,
+    metadata: Option<ShoutMetadata>,
+    throttle: Option<ShoutMetadataThrottle>,
+    on_event: Option<EventCallback>,
+    rng: u64,
+}
+
+impl ShoutSupervisor {
+    pub fn new(conn: ShoutConn) -> ShoutSupervisor {
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0);
+        ShoutSupervisor {
+            conn,
+            backoff: ShoutBackoff::default(),
+            metadata: None,
+            throttle: None,
+            on_event: None,
+            rng: seed | 1,
+        }
+    }
+
+    pub fn backoff(mut self, backoff: ShoutBackoff) -> ShoutSupervisor {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Routes `set_metadata` through `throttle`. After reconnecting, the throttle's latest value,
+    /// pending or sent, is re-applied.
+    pub fn throttle(mut self, throttle: ShoutMetadataThrottle) -> ShoutSupervisor {
+        self.throttle = Some(throttle);
+        self
+    }
+
+    /// Sets a callback invoked for every disconnection and reconnection attempt.
+    pub fn on_event<F>(mut self, on_event: F) -> ShoutSupervisor
+        where F: FnMut(&ShoutSupervisorEvent) + Send + 'static
+    {
+        self.on_event = Some(Box::new(on_event));
+        self
+    }
+
+    pub fn conn(&self) -> &ShoutConn {
+        &self.conn
+    }
+
+    pub fn metadata_throttle(&self) -> Option<&ShoutMetadataThrottle> {
+        self.throttle.as_ref()
+    }
+
+    pub fn into_inner(self) -> ShoutConn {
+        self.conn
+    }
+
+    /// Sends data, reconnecting and retrying it if the connection was lost. Fails with the last
+    /// error once the attempt budget is used up.
+    pub fn send(&mut self, data: &[u8]) -> Result<(), ShoutConnError> {
+        let mut attempt = 0;
+        loop {
+            let err = match self.conn.send(data) {
+                Ok(()) => return Ok(()),
+                Err(e) => e,
+            };
+            match err.code() {
+                ShoutErr::Socket | ShoutErr::Unconnected => {}
+                _ => return Err(err.into()),
+            }
+            self.emit(&ShoutSupervisorEvent::Disconnected(&err));
+            self.reconnect(&mut attempt, err.into())?;
+        }
+    }
+
+    /// Sets metadata on the server and remembers it to re-apply after reconnecting. With a
+    /// throttle, the update may instead be held back until `flush_metadata`.
+    pub fn set_metadata(&mut self, metadata: &ShoutMetadata) -> Result<(), ShoutConnError> {
+        if let Some(ref mut throttle) = self.throttle {
+            return throttle.update(&self.conn, metadata).map(|_| ());
+        }
+        self.metadata = Some(metadata.clone());
+        self.conn.set_metadata(metadata)
+    }
+
+    /// Sends the metadata held back by the throttle once its window has passed. Returns whether
+    /// anything was sent.
+    pub fn flush_metadata(&mut self) -> Result<bool, ShoutConnError> {
+        match self.throttle {
+            Some(ref mut throttle) => throttle.flush(&self.conn),
+            None => Ok(false),
+        }
+    }
+
+    /// Reconnects, counting attempts on from `attempt`. Fails with the last error, starting
+    /// with `error`, once the budget is used up.
+    fn reconnect(&mut self, attempt: &mut u32, mut error: ShoutConnError) -> Result<(), ShoutConnError> {
+        loop {
+            if self.backoff.max_attempts.is_some_and(|max| *attempt >= max) {
+                self.emit(&ShoutSupervisorEvent::GaveUp { attempts: *attempt });
+                return Err(error);
+            }
+            *attempt += 1;
+            let attempt = *attempt;
+            let jitter = self.next_jitter();
+            let delay = self.backoff.delay(attempt, jitter);
+            self.emit(&ShoutSupervisorEvent::Attempt { attempt, delay });
+            thread::sleep(delay);
+
+            match self.conn.reconnect() {
+                Ok(()) => {
+                    self.emit(&ShoutSupervisorEvent::Reconnected { attempt });
+                    if let Err(e) = self.reapply_metadata() {
+                        self.emit(&ShoutSupervisorEvent::MetadataFailed(&e));
+                    }
+                    return Ok(());
+                }
+                Err(e) => {
+                    self.emit(&ShoutSupervisorEvent::AttemptFailed { attempt, error: &e });
+                    error = e;
+                }
+            }
+        }
+    }
+
+    fn reapply_metadata(&mut self) -> Result<(), ShoutConnError> {
+        match (self.throttle.as_mut(), self.metadata.as_ref()) {
+            (Some(throttle), _) => throttle.reapply(&self.conn),
+            (None, Some(metadata)) => self.conn.set_metadata(metadata),
+            (None, None) => Ok(()),
+        }
+    }
+
+    fn emit(&mut self, event: &ShoutSupervisorEvent) {
+        if let Some(ref mut on_event) = self.on_event {
+            on_event(event);
+        }
+    }
+
+    /// Returns a pseudo-random number between -1 and 1 from a xorshift generator.
+    fn next_jitter(&mut self) -> f64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use ShoutConnBuilder;
+
+    #[test]
+    fn backoff_grows_and_caps() {
+        let backoff = ShoutBackoff {
+            initial: Duration::from_millis(100),
+            max: Duration::from_millis(350),
+            multiplier: 2.0,
+            jitter: 0.5,
+            max_attempts: None,
+        };
+        assert_eq!(backoff.delay(1, 0.0), Duration::from_millis(100));
+        assert_eq!(backoff.delay(2, 0.0), Duration::from_millis(200));
+        assert_eq!(backoff.delay(3, 0.0), Duration::from_millis(350));
+        assert_eq!(backoff.delay(2, 1.0), Duration::from_millis(300));
+        assert_eq!(backoff.delay(2, -1.0), Duration::from_millis(100));
+
+        let unbounded = ShoutBackoff { max: Duration::MAX, multiplier: 10.0, ..backoff };
+        assert_eq!(unbounded.delay(100, 1.0), Duration::MAX);
+    }
+
+    #[test]
+    fn gives_up_after_budget() {
+        let _serial = ::test_support::serial();
+        let conn = ShoutConnBuilder::new()
+            .host(String::from("127.0.0.1"))
+            .port(::test_support::closed_port())
+            .password(String::from("pw"))
+            .configure()
+            .unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let log = events.clone();
+        let mut supervisor = ShoutSupervisor::new(conn)
+            .backoff(ShoutBackoff {
+                initial: Duration::from_millis(1),
+                max_attempts: Some(3),
+                ..ShoutBackoff::default()
+            })
+            .on_event(move |e| log.lock().unwrap().push(format!("{:?}", e)));
+
+        match supervisor.send(b"data") {
+            Err(ShoutConnError::ShoutError(e)) => assert_eq!(e.code(), ShoutErr::NoConnect),
+            _ => panic!("expected the supervisor to give up"),
+        }
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 8);
+        assert!(events[0].starts_with("Disconnected"));
+        assert!(events[1].starts_with("Attempt { attempt: 1"));
+        assert!(events[2].starts_with("AttemptFailed { attempt: 1"));
+        assert_eq!(events[7], "GaveUp { attempts: 3 }");
+    }
+
+    #[test]
+    fn reconnects_and_resends() {
+        let server = ::test_support::StandIn::dropping(1);
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let log = events.clone();
+        let mut supervisor = ShoutSupervisor::new(server.builder().build().unwrap())
+            .backoff(ShoutBackoff {
+                initial: Duration::from_millis(1),
+                ..ShoutBackoff::default()
+            })
+            .on_event(move |e| log.lock().unwrap().push(format!("{:?}", e)));
+        let mut metadata = ShoutMetadata::new();
+        metadata.set_song(String::from("Song")).unwrap();
+        supervisor.set_metadata(&metadata).unwrap();
+
+        // The first buffer is lost with the connection; the failure shows on the next one.
+        supervisor.send(b"lost").unwrap();
+        server.wait_for_drop();
+        thread::sleep(Duration::from_millis(20));
+        supervisor.send(b"resent").unwrap();
+        supervisor.send(b" and more").unwrap();
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 3);
+        assert!(events[0].starts_with("Disconnected"));
+        assert!(events[1].starts_with("Attempt { attempt: 1"));
+        assert_eq!(events[2], "Reconnected { attempt: 1 }");
+        supervisor.into_inner().close().unwrap();
+        assert_eq!(server.songs(), vec!["Song", "Song"]);
+        assert_eq!(server.finish(), b"resent and more");
+    }
+    #[test]
+    fn reapplies_pending_throttled_metadata() {
+        let server = ::test_support::StandIn::dropping(1);
+        let mut supervisor = ShoutSupervisor::new(server.builder().build().unwrap())
+            .backoff(ShoutBackoff {
+                initial: Duration::from_millis(1),
+                ..ShoutBackoff::default()
+            })
+            .throttle(ShoutMetadataThrottle::new(Duration::from_secs(60)));
+        for title in &["A", "B"] {
+            let mut metadata = ShoutMetadata::new();
+            metadata.set_song(title.to_string()).unwrap();
+            supervisor.set_metadata(&metadata).unwrap();
+        }
+        assert!(!supervisor.flush_metadata().unwrap());
+        assert!(supervisor.metadata_throttle().unwrap().pending().is_some());
+
+        supervisor.send(b"lost").unwrap();
+        server.wait_for_drop();
+        thread::sleep(Duration::from_millis(20));
+        supervisor.send(b"resent").unwrap();
+
+        assert_eq!(supervisor.metadata_throttle().unwrap().pending(), None);
+        supervisor.into_inner().close().unwrap();
+        assert_eq!(server.songs(), vec!["A", "B"]);
+        assert_eq!(server.finish(), b"resent");
+    }
+}
@@ -0,0 +1,174 @@
+//! Fixtures shared by the tests of every module.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use {ShoutConnBuilder, ShoutFormat, ShoutProtocol, ShoutTLS};
+
+/// Serializes tests which create libshout handles so the global instance count can be asserted
+/// on.
+pub fn serial() -> MutexGuard<'static, ()> {
+    static LOCK: Mutex<()> = Mutex::new(());
+    LOCK.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Returns a local port with nothing listening on it.
+pub fn closed_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+/// Returns a builder for an MP3 stream to a local server.
+pub fn local_builder(port: u16) -> ShoutConnBuilder {
+    ShoutConnBuilder::new()
+        .host(String::from("127.0.0.1"))
+        .port(port)
+        .password(String::from("pw"))
+        .mount(String::from("/test.mp3"))
+        .protocol(ShoutProtocol::HTTP)
+        .tls(ShoutTLS::Disabled)
+        .format(ShoutFormat::MP3)
+}
+
+/// Returns the percent-decoded value of `key` in a query string.
+fn query_param(query: &str, key: &str) -> Option<String> {
+    let value = query.split('&').find_map(|pair| pair.strip_prefix(key)?.strip_prefix('='))?;
+    let mut bytes = Vec::new();
+    let mut iter = value.bytes();
+    while let Some(b) = iter.next() {
+        match b {
+            b'%' => {
+                let hex: String = iter.by_ref().take(2).map(char::from).collect();
+                bytes.push(u8::from_str_radix(&hex, 16).unwrap());
+            }
+            b'+' => bytes.push(b' '),
+            b => bytes.push(b),
+        }
+    }
+    Some(String::from_utf8(bytes).unwrap())
+}
+
+/// Reads the head of a request and returns its request line.
+fn read_request(reader: &mut BufReader<TcpStream>) -> String {
+    let mut request = String::new();
+    reader.read_line(&mut request).unwrap();
+    let mut line = String::new();
+    while reader.read_line(&mut line).unwrap() > 0 && line != "\r\n" {
+        line.clear();
+    }
+    request
+}
+
+/// A local server standing in for Icecast. The test lock is held for as long as it is alive.
+pub struct StandIn {
+    port: u16,
+    server: JoinHandle<Vec<u8>>,
+    songs: Arc<Mutex<Vec<String>>>,
+    dropped: Receiver<()>,
+    _serial: MutexGuard<'static, ()>,
+}
+
+impl StandIn {
+    /// Takes the test lock and starts a server which accepts one source connection and collects
+    /// the stream until it is closed. Metadata updates, which libshout sends as separate
+    /// requests, are answered until then, and refused for Ogg mounts.
+    pub fn start() -> StandIn {
+        StandIn::dropping(0)
+    }
+
+    /// Like `start`, but drops each of the first `drops` source connections as soon as data
+    /// arrives on it, resetting the connection.
+    pub fn dropping(mut drops: usize) -> StandIn {
+        let serial = serial();
+        let (dropped_tx, dropped) = mpsc::channel();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let songs = Arc::new(Mutex::new(Vec::new()));
+        let server = {
+            let songs = songs.clone();
+            thread::spawn(move || {
+                let mut source: Option<JoinHandle<Vec<u8>>> = None;
+                while !source.as_ref().is_some_and(JoinHandle::is_finished) {
+                    let stream = match listener.accept() {
+                        Ok((stream, _)) => stream,
+                        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                            thread::sleep(Duration::from_millis(1));
+                            continue;
+                        }
+                        Err(e) => panic!("accept failed: {}", e),
+                    };
+                    stream.set_nonblocking(false).unwrap();
+                    let mut reader = BufReader::new(stream);
+                    let request = read_request(&mut reader);
+                    let query = request.strip_prefix("GET /admin/metadata?").and_then(|q| q.split(' ').next());
+                    match query {
+                        // Like Icecast, refuse updates for Ogg mounts, which carry them in the stream.
+                        Some(query) if query_param(query, "mount").is_some_and(|m| m.ends_with(".ogg")) => {
+                            reader.get_mut().write_all(b"HTTP/1.0 400 Bad Request\r\n\r\n").unwrap();
+                        }
+                        Some(query) => {
+                            songs.lock().unwrap().push(query_param(query, "song").unwrap_or_default());
+                            reader.get_mut().write_all(b"HTTP/1.0 200 OK\r\n\r\n").unwrap();
+                        }
+                        None if drops > 0 => {
+                            drops -= 1;
+                            reader.get_mut().write_all(b"HTTP/1.0 200 OK\r\n\r\n").unwrap();
+                            let dropped = dropped_tx.clone();
+                            thread::spawn(move || {
+                                // Closing with the data unread resets the connection.
+                                reader.get_ref().peek(&mut [0]).unwrap();
+                                drop(reader);
+                                let _ = dropped.send(());
+                            });
+                        }
+                        None => {
+                            reader.get_mut().write_all(b"HTTP/1.0 200 OK\r\n\r\n").unwrap();
+                            source = Some(thread::spawn(move || {
+                                let mut body = Vec::new();
+                                reader.read_to_end(&mut body).unwrap();
+                                body
+                            }));
+                        }
+                    }
+                }
+                source.unwrap().join().unwrap()
+            })
+        };
+        StandIn {
+            port,
+            server,
+            songs,
+            dropped,
+            _serial: serial,
+        }
+    }
+
+    /// Returns the port the server listens on.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Returns a builder for an MP3 stream to the server.
+    pub fn builder(&self) -> ShoutConnBuilder {
+        local_builder(self.port)
+    }
+
+    /// Returns the songs set by the metadata updates received so far.
+    pub fn songs(&self) -> Vec<String> {
+        self.songs.lock().unwrap().clone()
+    }
+
+    /// Waits for the server to drop a source connection.
+    pub fn wait_for_drop(&self) {
+        self.dropped.recv().unwrap();
+    }
+
+    /// Waits for the source connection to be closed and returns the data streamed over it.
+    pub fn finish(self) -> Vec<u8> {
+        self.server.join().unwrap()
+    }
+}
@@ -0,0 +1,161 @@
+//! Rate limiting and deduplication of metadata updates.
+
+use std::time::{Duration, Instant};
+
+use {ShoutConn, ShoutConnError, ShoutMetadata};
+
+/// Type representing what `ShoutMetadataThrottle::update` did with an update
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ShoutMetadataUpdate {
+    /// The metadata was sent to the server
+    Sent,
+    /// The metadata is already on the server, or already waiting to be sent
+    Duplicate,
+    /// The metadata arrived too soon after the last update and is waiting to be sent by `flush`
+    Deferred,
+}
+
+/// Sits in front of `ShoutConn::set_metadata`, sending at most one update per window. Updates
+/// identical to the latest value are skipped, and a burst of updates within a window is merged
+/// into its last one. The latest value is kept to be re-applied after a reconnect, which
+/// `ShoutSupervisor::throttle` does automatically.
+#[derive(Clone, Debug)]
+pub struct ShoutMetadataThrottle {
+    window: Duration,
+    last_sent: Option<ShoutMetadata>,
+    last_time: Option<Instant>,
+    pending: Option<ShoutMetadata>,
+}
+
+impl ShoutMetadataThrottle {
+    pub fn new(window: Duration) -> ShoutMetadataThrottle {
+        ShoutMetadataThrottle {
+            window,
+            last_sent: None,
+            last_time: None,
+            pending: None,
+        }
+    }
+
+    /// Sends the metadata now if the window since the last update has passed, and otherwise
+    /// keeps it for `flush`. If sending fails, the metadata is kept for `flush` or `reapply`.
+    pub fn update(&mut self, conn: &ShoutConn, metadata: &ShoutMetadata) -> Result<ShoutMetadataUpdate, ShoutConnError> {
+        self.update_at(conn, metadata, Instant::now())
+    }
+
+    /// Sends the pending metadata once its window has passed. Returns whether anything was sent.
+    pub fn flush(&mut self, conn: &ShoutConn) -> Result<bool, ShoutConnError> {
+        self.flush_at(conn, Instant::now())
+    }
+
+    /// Sends the latest metadata regardless of the window, for use after reconnecting.
+    pub fn reapply(&mut self, conn: &ShoutConn) -> Result<(), ShoutConnError> {
+        match self.pending.take().or_else(|| self.last_sent.take()) {
+            Some(metadata) => self.send(conn, metadata, Instant::now()),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns when `flush` should next be called, if anything is pending.
+    pub fn next_flush(&self) -> Option<Instant> {
+        self.pending.as_ref()?;
+        Some(self.last_time.map_or_else(Instant::now, |t| t + self.window))
+    }
+
+    /// Returns the metadata waiting to be sent.
+    pub fn pending(&self) -> Option<&ShoutMetadata> {
+        self.pending.as_ref()
+    }
+
+    /// Returns the metadata last sent to the server.
+    pub fn last_sent(&self) -> Option<&ShoutMetadata> {
+        self.last_sent.as_ref()
+    }
+
+    fn update_at(&mut self, conn: &ShoutConn, metadata: &ShoutMetadata, now: Instant) -> Result<ShoutMetadataUpdate, ShoutConnError> {
+        if self.pending.as_ref() == Some(metadata) {
+            return Ok(ShoutMetadataUpdate::Duplicate);
+        }
+        if self.last_sent.as_ref() == Some(metadata) {
+            // Reverting a pending update leaves the server as it is.
+            self.pending = None;
+            return Ok(ShoutMetadataUpdate::Duplicate);
+        }
+        if self.last_time.is_some_and(|t| now < t + self.window) {
+            self.pending = Some(metadata.clone());
+            return Ok(ShoutMetadataUpdate::Deferred);
+        }
+        self.send(conn, metadata.clone(), now)?;
+        Ok(ShoutMetadataUpdate::Sent)
+    }
+
+    fn flush_at(&mut self, conn: &ShoutConn, now: Instant) -> Result<bool, ShoutConnError> {
+        if self.last_time.is_some_and(|t| now < t + self.window) {
+            return Ok(false);
+        }
+        match self.pending.take() {
+            Some(metadata) => self.send(conn, metadata, now).map(|()| true),
+            None => Ok(false),
+        }
+    }
+
+    fn send(&mut self, conn: &ShoutConn, metadata: ShoutMetadata, now: Instant) -> Result<(), ShoutConnError> {
+        match conn.set_metadata(&metadata) {
+            Ok(()) => {
+                self.last_sent = Some(metadata);
+                self.last_time = Some(now);
+                Ok(())
+            }
+            Err(e) => {
+                self.pending = Some(metadata);
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(title: &str) -> ShoutMetadata {
+        ShoutMetadata::from_pairs(vec![("song", title)]).unwrap()
+    }
+
+    #[test]
+    fn merges_bursts_and_skips_duplicates() {
+        let server = ::test_support::StandIn::start();
+        let conn = server.builder().build().unwrap();
+        let mut throttle = ShoutMetadataThrottle::new(Duration::from_secs(5));
+        let start = Instant::now();
+
+        assert_eq!(throttle.update_at(&conn, &song("A"), start).unwrap(), ShoutMetadataUpdate::Sent);
+        assert_eq!(throttle.update_at(&conn, &song("A"), start).unwrap(), ShoutMetadataUpdate::Duplicate);
+        assert_eq!(throttle.update_at(&conn, &song("B"), start).unwrap(), ShoutMetadataUpdate::Deferred);
+        assert_eq!(throttle.update_at(&conn, &song("C"), start).unwrap(), ShoutMetadataUpdate::Deferred);
+        assert_eq!(throttle.update_at(&conn, &song("C"), start).unwrap(), ShoutMetadataUpdate::Duplicate);
+        assert_eq!(throttle.next_flush(), Some(start + Duration::from_secs(5)));
+
+        assert!(!throttle.flush_at(&conn, start + Duration::from_secs(1)).unwrap());
+        assert!(throttle.flush_at(&conn, start + Duration::from_secs(5)).unwrap());
+        assert_eq!(throttle.last_sent(), Some(&song("C")));
+        assert_eq!(throttle.pending(), None);
+        assert_eq!(throttle.next_flush(), None);
+        assert_eq!(server.songs(), vec!["A", "C"]);
+
+        conn.close().unwrap();
+        server.finish();
+    }
+
+    #[test]
+    fn keeps_failed_update_for_reapply() {
+        let _serial = ::test_support::serial();
+        let conn = ::test_support::local_builder(::test_support::closed_port()).configure().unwrap();
+        let mut throttle = ShoutMetadataThrottle::new(Duration::from_secs(5));
+        assert!(throttle.update(&conn, &song("A")).is_err());
+        assert_eq!(throttle.pending(), Some(&song("A")));
+        assert!(throttle.reapply(&conn).is_err());
+        assert_eq!(throttle.pending(), Some(&song("A")));
+        assert_eq!(throttle.last_sent(), None);
+    }
+}
@@ -0,0 +1,101 @@
+//! Pacing which fits into the caller's own event loop or can be interrupted.
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use ShoutConn;
+
+/// Type representing why `ShoutConn::sync_timeout` returned
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ShoutSyncStatus {
+    /// The server requires more data
+    Ready,
+    /// The timeout passed before the server required more data
+    TimedOut,
+    /// The cancellation signal was raised
+    Cancelled,
+}
+
+/// A cancellation signal which may be cloned and raised from any thread, waking up waiters
+/// immediately. Once raised it stays raised.
+#[derive(Clone, Debug, Default)]
+pub struct ShoutCancel {
+    inner: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl ShoutCancel {
+    pub fn new() -> ShoutCancel {
+        ShoutCancel::default()
+    }
+
+    /// Raises the signal, waking up everything waiting on it.
+    pub fn cancel(&self) {
+        let (ref cancelled, ref cond) = *self.inner;
+        *cancelled.lock().unwrap_or_else(|e| e.into_inner()) = true;
+        cond.notify_all();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.inner.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Waits for `delay`, giving up after `timeout` or when the signal is raised.
+    pub(crate) fn wait(&self, delay: Duration, timeout: Duration) -> ShoutSyncStatus {
+        let (ref cancelled, ref cond) = *self.inner;
+        let guard = cancelled.lock().unwrap_or_else(|e| e.into_inner());
+        let (guard, _) = cond
+            .wait_timeout_while(guard, delay.min(timeout), |c| !*c)
+            .unwrap_or_else(|e| e.into_inner());
+        if *guard {
+            ShoutSyncStatus::Cancelled
+        } else if timeout < delay {
+            ShoutSyncStatus::TimedOut
+        } else {
+            ShoutSyncStatus::Ready
+        }
+    }
+}
+
+impl ShoutConn {
+    /// Sleeps the thread until the server requires more data, like `sync`, but for at most
+    /// `timeout`, and returns as soon as `cancel` is raised.
+    pub fn sync_timeout(&self, timeout: Duration, cancel: &ShoutCancel) -> ShoutSyncStatus {
+        cancel.wait(self.delay(), timeout)
+    }
+
+    /// Returns when the server will next require data, to be waited on by the caller's own
+    /// event loop in place of `sync`.
+    pub fn next_deadline(&self) -> Instant {
+        Instant::now() + self.delay()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn wait_reports_why_it_returned() {
+        let cancel = ShoutCancel::new();
+        let hour = Duration::from_secs(3600);
+        assert_eq!(cancel.wait(Duration::from_millis(1), hour), ShoutSyncStatus::Ready);
+        assert_eq!(cancel.wait(hour, Duration::from_millis(1)), ShoutSyncStatus::TimedOut);
+
+        let raiser = cancel.clone();
+        let handle = thread::spawn(move || raiser.cancel());
+        assert_eq!(cancel.wait(hour, hour), ShoutSyncStatus::Cancelled);
+        handle.join().unwrap();
+        assert!(cancel.is_cancelled());
+    }
+
+    #[test]
+    fn unconnected_handle_needs_no_wait() {
+        let _serial = ::test_support::serial();
+        let conn = ::test_support::local_builder(::test_support::closed_port()).configure().unwrap();
+        assert_eq!(conn.delay(), Duration::from_millis(0));
+        assert!(conn.next_deadline() <= Instant::now());
+        let status = conn.sync_timeout(Duration::from_secs(1), &ShoutCancel::new());
+        assert_eq!(status, ShoutSyncStatus::Ready);
+    }
+}
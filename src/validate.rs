@@ -0,0 +1,193 @@
+//! Pure Rust checks of a `ShoutConnBuilder`, run before any FFI call.
+
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::path::Path;
+
+use {ShoutConnBuilder, ShoutFormat, ShoutProtocol, ShoutTLS, ShoutUsage};
+
+/// Type representing a single problem found by `ShoutConnBuilder::validate`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ShoutValidationError {
+    /// The mount point doesn't start with `/`
+    MountWithoutSlash(String),
+    /// The port is zero
+    ZeroPort,
+    /// No password is set, but the protocol requires one
+    MissingPassword(ShoutProtocol),
+    /// TLS is enabled, but the CA file doesn't exist
+    MissingCaFile(String),
+    /// TLS is enabled, but the CA directory doesn't exist
+    MissingCaDirectory(String),
+    /// The client certificate can't be read
+    UnreadableClientCert(String),
+    /// The protocol can't carry the format
+    IncompatibleFormat(ShoutFormat, ShoutProtocol),
+    /// The format and usage combination isn't accepted by libshout
+    InvalidContentFormat(String),
+    /// A flag which libshout only accepts as 0 or 1 has another value
+    InvalidFlag(&'static str, u32),
+    /// A string parameter contains an interior NUL byte
+    InteriorNul(&'static str),
+    /// A custom meta key contains characters other than lowercase ASCII letters and digits
+    InvalidMetaKey(String),
+}
+
+impl fmt::Display for ShoutValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ShoutValidationError::MountWithoutSlash(ref m) => write!(f, "mount {:?} must start with '/'", m),
+            ShoutValidationError::ZeroPort => write!(f, "port must not be 0"),
+            ShoutValidationError::MissingPassword(p) => write!(f, "a password is required with {:?}", p),
+            ShoutValidationError::MissingCaFile(ref p) => write!(f, "CA file {:?} does not exist", p),
+            ShoutValidationError::MissingCaDirectory(ref p) => write!(f, "CA directory {:?} does not exist", p),
+            ShoutValidationError::UnreadableClientCert(ref p) => write!(f, "client certificate {:?} is not readable", p),
+            ShoutValidationError::IncompatibleFormat(format, protocol) => {
+                write!(f, "{:?} streams can't be sent with {:?}", format, protocol)
+            }
+            ShoutValidationError::InvalidContentFormat(ref m) => f.write_str(m),
+            ShoutValidationError::InvalidFlag(name, v) => write!(f, "{} must be 0 or 1, not {}", name, v),
+            ShoutValidationError::InteriorNul(name) => write!(f, "{} contains a NUL byte", name),
+            ShoutValidationError::InvalidMetaKey(ref k) => write!(f, "invalid meta key {:?}", k),
+        }
+    }
+}
+
+impl Error for ShoutValidationError {}
+
+impl ShoutConnBuilder {
+    /// Checks the builder for misconfigurations libshout would reject, reporting every problem
+    /// found rather than stopping at the first. No FFI calls are made, but the TLS and client
+    /// certificate paths are checked on the filesystem.
+    pub fn validate(&self) -> Result<(), Vec<ShoutValidationError>> {
+        let mut errors = Vec::new();
+
+        macro_rules! check_nul {
+            ($($field:ident),+) => (
+                $(
+                    if self.$field.as_ref().map_or(false, |s| s.contains('\0')) {
+                        errors.push(ShoutValidationError::InteriorNul(stringify!($field)));
+                    }
+                )+
+            );
+        }
+        check_nul!(host, agent, ca_directory, ca_file, allowed_ciphers, user, client_cert, mount,
+                   dumpfile);
+        if self.password.as_ref().is_some_and(|p| p.expose().contains('\0')) {
+            errors.push(ShoutValidationError::InteriorNul("password"));
+        }
+        for ai in &self.audio_info {
+            let (key, value) = ai.clone().into_key_value();
+            if key.contains('\0') || value.contains('\0') {
+                errors.push(ShoutValidationError::InteriorNul("audio_info"));
+            }
+        }
+        for meta in &self.meta {
+            let (key, value) = meta.clone().into_key_value();
+            if !key.is_valid() {
+                errors.push(ShoutValidationError::InvalidMetaKey(key.as_str().to_owned()));
+            }
+            if value.contains('\0') {
+                errors.push(ShoutValidationError::InteriorNul("meta"));
+            }
+        }
+
+        if let Some(ref mount) = self.mount {
+            if !mount.starts_with('/') {
+                errors.push(ShoutValidationError::MountWithoutSlash(mount.clone()));
+            }
+        }
+        if self.port == Some(0) {
+            errors.push(ShoutValidationError::ZeroPort);
+        }
+
+        let protocol = self.protocol.unwrap_or(ShoutProtocol::HTTP);
+        if self.password.is_none() && protocol != ShoutProtocol::RoarAudio {
+            errors.push(ShoutValidationError::MissingPassword(protocol));
+        }
+
+        if self.tls != Some(ShoutTLS::Disabled) {
+            if let Some(ref file) = self.ca_file {
+                if !Path::new(file).is_file() {
+                    errors.push(ShoutValidationError::MissingCaFile(file.clone()));
+                }
+            }
+            if let Some(ref dir) = self.ca_directory {
+                if !Path::new(dir).is_dir() {
+                    errors.push(ShoutValidationError::MissingCaDirectory(dir.clone()));
+                }
+            }
+        }
+        if let Some(ref cert) = self.client_cert {
+            if File::open(cert).is_err() {
+                errors.push(ShoutValidationError::UnreadableClientCert(cert.clone()));
+            }
+        }
+
+        let format = self.format.unwrap_or(ShoutFormat::Ogg);
+        if protocol == ShoutProtocol::Icy && format != ShoutFormat::MP3 {
+            errors.push(ShoutValidationError::IncompatibleFormat(format, protocol));
+        }
+        if let Err(m) = format.content_format(self.usage.unwrap_or_else(|| ShoutUsage::Audio.into())) {
+            errors.push(ShoutValidationError::InvalidContentFormat(m));
+        }
+
+        if let Some(public) = self.public.filter(|&p| p > 1) {
+            errors.push(ShoutValidationError::InvalidFlag("public", public));
+        }
+        if let Some(nonblocking) = self.nonblocking.filter(|&n| n > 1) {
+            errors.push(ShoutValidationError::InvalidFlag("nonblocking", nonblocking));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {ShoutAudioInfo, ShoutMeta, ShoutUsage};
+
+    #[test]
+    fn valid_builder_passes() {
+        let builder = ShoutConnBuilder::new()
+            .host(String::from("localhost"))
+            .password(String::from("pw"))
+            .mount(String::from("/live.mp3"))
+            .protocol(ShoutProtocol::Icy)
+            .format(ShoutFormat::MP3);
+        assert_eq!(builder.validate(), Ok(()));
+    }
+
+    #[test]
+    fn reports_every_problem() {
+        let builder = ShoutConnBuilder::new()
+            .host(String::from("local\0host"))
+            .port(0)
+            .mount(String::from("live.ogg"))
+            .protocol(ShoutProtocol::Icy)
+            .format(ShoutFormat::Ogg)
+            .usage(ShoutUsage::Audio | ShoutUsage::Visual)
+            .tls(ShoutTLS::RFC2818)
+            .ca_file(String::from("/nonexistent/ca.pem"))
+            .public(2)
+            .add_meta(ShoutMeta::Custom(String::from("ice-name"), String::from("x")))
+            .add_audio_info(ShoutAudioInfo::Custom(String::from("ice-foo"), String::from("bar\0")));
+        assert_eq!(builder.validate(), Err(vec![
+            ShoutValidationError::InteriorNul("host"),
+            ShoutValidationError::InteriorNul("audio_info"),
+            ShoutValidationError::InvalidMetaKey(String::from("ice-name")),
+            ShoutValidationError::MountWithoutSlash(String::from("live.ogg")),
+            ShoutValidationError::ZeroPort,
+            ShoutValidationError::MissingPassword(ShoutProtocol::Icy),
+            ShoutValidationError::MissingCaFile(String::from("/nonexistent/ca.pem")),
+            ShoutValidationError::IncompatibleFormat(ShoutFormat::Ogg, ShoutProtocol::Icy),
+            ShoutValidationError::InvalidFlag("public", 2),
+        ]));
+    }
+}
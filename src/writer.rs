@@ -0,0 +1,90 @@
+//! `std::io::Write` support, so streams can be fed with `io::copy`.
+
+use std::io::{self, Write};
+use std::thread;
+use std::time::Duration;
+
+use ShoutConn;
+
+/// How long `flush` waits between attempts to drain a nonblocking write queue.
+const FLUSH_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A wrapper around `ShoutConn` implementing `io::Write`. Errors carry the libshout message, and
+/// `flush` blocks until the write queue of a nonblocking connection has been drained.
+pub struct ShoutWriter {
+    conn: ShoutConn,
+    sync: bool,
+    written: bool,
+}
+
+impl ShoutWriter {
+    pub fn new(conn: ShoutConn) -> ShoutWriter {
+        ShoutWriter {
+            conn,
+            sync: false,
+            written: false,
+        }
+    }
+
+    /// Sets whether to call `ShoutConn::sync` between writes, pacing them to the stream's
+    /// bitrate. Disabled by default.
+    pub fn sync(mut self, sync: bool) -> ShoutWriter {
+        self.sync = sync;
+        self
+    }
+
+    pub fn conn(&self) -> &ShoutConn {
+        &self.conn
+    }
+
+    pub fn into_inner(self) -> ShoutConn {
+        self.conn
+    }
+}
+
+impl Write for ShoutWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.sync && self.written {
+            self.conn.sync();
+        }
+        self.conn.send(buf)?;
+        self.written = true;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        while self.conn.flush_queue()? > 0 {
+            thread::sleep(FLUSH_INTERVAL);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ShoutErr;
+
+    #[test]
+    fn copies_into_stand_in_server() {
+        let server = ::test_support::StandIn::start();
+        let conn = server.builder().build().unwrap();
+        let mut writer = ShoutWriter::new(conn).sync(true);
+        let mut data: &[u8] = b"first chunk, second chunk";
+        assert_eq!(io::copy(&mut data, &mut writer).unwrap(), 25);
+        writer.flush().unwrap();
+        drop(writer);
+        assert_eq!(server.finish(), b"first chunk, second chunk");
+    }
+
+    #[test]
+    fn errors_keep_libshout_message() {
+        let _serial = ::test_support::serial();
+        let conn = ::test_support::local_builder(::test_support::closed_port()).configure().unwrap();
+        let err = ShoutWriter::new(conn).write(b"data").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+        let inner = err.into_inner().unwrap().downcast::<::ShoutError>().unwrap();
+        assert_eq!(inner.code(), ShoutErr::Unconnected);
+        assert!(inner.to_string().starts_with("send failed: "));
+    }
+}